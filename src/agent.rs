@@ -0,0 +1,925 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::ValueEnum;
+use jiff::Timestamp;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::debug;
+use tracing::warn;
+use zeroize::Zeroize;
+use zeroize::Zeroizing;
+
+use crate::error::HemliError;
+use crate::model::StoredSecret;
+use crate::refresh;
+use crate::refresh::RefreshOutcome;
+use crate::store::Backend;
+use crate::store::SecretStore;
+
+/// How often the agent looks for cached secrets that are due for a refresh.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The longest the agent waits before retrying a secret whose refresh keeps
+/// failing.
+const MAX_REFRESH_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// How often the agent checks whether it has been idle for too long.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Source commands the agent runs in parallel when refreshing.
+const REFRESH_JOBS: usize = 4;
+
+/// How long a client waits for the agent to take a request and answer it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The agent's socket for `backend`, in the user's runtime directory (or the
+/// data directory if there is none).
+pub fn socket_path(backend: Backend) -> PathBuf {
+    let dir = dirs::runtime_dir()
+        .or_else(dirs::data_dir)
+        .unwrap_or_else(|| PathBuf::from("."));
+    let name = backend
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default();
+    dir.join("hemli").join(format!("agent-{name}.sock"))
+}
+
+/// Whether an agent is listening on `socket`. A socket file alone may have
+/// been left behind by an agent that did not exit cleanly.
+pub fn is_listening(socket: &Path) -> bool {
+    UnixStream::connect(socket).is_ok()
+}
+
+/// A request from the CLI, sent as one line of JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    Get {
+        namespace: String,
+        name: String,
+    },
+    Set {
+        namespace: String,
+        name: String,
+        secret: Box<StoredSecret>,
+    },
+    Delete {
+        namespace: String,
+        name: String,
+    },
+    List,
+    Status,
+    Lock,
+    Unlock {
+        passphrase: Option<Zeroizing<String>>,
+    },
+    Stop,
+}
+
+/// The agent's answer to a [`Request`], sent as one line of JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Secret {
+        secret: Option<Box<StoredSecret>>,
+    },
+    Names {
+        names: Option<Vec<(String, String)>>,
+    },
+    Status {
+        pid: u32,
+        cached: usize,
+        locked: bool,
+    },
+    Done,
+    Error {
+        message: String,
+    },
+}
+
+/// Send `request` to the agent listening on `socket` and wait for its answer,
+/// or return `None` if no agent is listening. An error answer, or none within
+/// [`REQUEST_TIMEOUT`], becomes [`HemliError::Agent`].
+pub fn request(socket: &Path, request: &Request) -> Result<Option<Response>, HemliError> {
+    request_within(socket, request, REQUEST_TIMEOUT)
+}
+
+fn request_within(
+    socket: &Path,
+    request: &Request,
+    timeout: Duration,
+) -> Result<Option<Response>, HemliError> {
+    let mut stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let timed_out = |e: std::io::Error| {
+        if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+            HemliError::Agent(format!("no answer within {timeout:?}"))
+        } else {
+            e.into()
+        }
+    };
+    // Requests and answers may carry a passphrase or secret values
+    let mut line = Zeroizing::new(serde_json::to_vec(request)?);
+    line.push(b'\n');
+    stream.write_all(&line).map_err(timed_out)?;
+    let mut reader = BufReader::new(stream);
+    let mut answer = Zeroizing::new(String::new());
+    reader.read_line(&mut answer).map_err(timed_out)?;
+    if answer.is_empty() {
+        return Err(HemliError::Agent(
+            "connection closed without an answer".into(),
+        ));
+    }
+    match serde_json::from_str(&answer)? {
+        Response::Error { message } => Err(HemliError::Agent(message)),
+        response => Ok(Some(response)),
+    }
+}
+
+/// A cache of stored secrets in front of a backend.
+pub struct Agent {
+    store: Box<dyn SecretStore>,
+    default_source_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    state: Mutex<State>,
+}
+
+struct State {
+    cache: HashMap<(String, String), StoredSecret>,
+    /// Set while locked: the secrets that were cached, to load again on
+    /// unlock.
+    locked: Option<Vec<(String, String)>>,
+    last_used: Instant,
+    /// Secrets a client has read since the agent last refreshed them. Only
+    /// these are refreshed ahead of expiry.
+    read: HashSet<(String, String)>,
+    /// Secrets whose last refresh failed, with the failure count and when
+    /// to try again.
+    backoff: HashMap<(String, String), (u32, Instant)>,
+}
+
+impl Agent {
+    pub fn new(
+        store: Box<dyn SecretStore>,
+        default_source_timeout: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            store,
+            default_source_timeout,
+            idle_timeout,
+            state: Mutex::new(State {
+                cache: HashMap::new(),
+                locked: None,
+                last_used: Instant::now(),
+                read: HashSet::new(),
+                backoff: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn handle(&self, request: Request) -> Response {
+        let result = match request {
+            Request::Status => {
+                let state = self.state.lock().unwrap();
+                Ok(Response::Status {
+                    pid: std::process::id(),
+                    cached: state.cache.len(),
+                    locked: state.locked.is_some(),
+                })
+            }
+            Request::Lock => {
+                self.lock();
+                Ok(Response::Done)
+            }
+            Request::Unlock { passphrase } => self
+                .unlock(passphrase.as_ref().map(|p| p.as_str()))
+                .map(|()| Response::Done),
+            // Handled by the connection, which has to answer before exiting
+            Request::Stop => Ok(Response::Done),
+            request => self.serve(request),
+        };
+        result.unwrap_or_else(|e| Response::Error {
+            message: e.to_string(),
+        })
+    }
+
+    /// Answer a request for the store from a client, unless the agent is
+    /// locked.
+    fn serve(&self, request: Request) -> Result<Response, HemliError> {
+        self.state.lock().unwrap().last_used = Instant::now();
+        let view = Cached(self);
+        match request {
+            Request::Get { namespace, name } => {
+                let secret = view.get(&namespace, &name)?;
+                if secret.is_some() {
+                    self.state.lock().unwrap().read.insert((namespace, name));
+                }
+                Ok(Response::Secret {
+                    secret: secret.map(Box::new),
+                })
+            }
+            Request::Set {
+                namespace,
+                name,
+                secret,
+            } => view
+                .set(&namespace, &name, &secret)
+                .map(|()| Response::Done),
+            Request::Delete { namespace, name } => {
+                view.delete(&namespace, &name).map(|()| Response::Done)
+            }
+            Request::List => view.list().map(|names| Response::Names { names }),
+            Request::Status | Request::Lock | Request::Unlock { .. } | Request::Stop => {
+                unreachable!("handled by Agent::handle")
+            }
+        }
+    }
+
+    fn ensure_unlocked(&self) -> Result<(), HemliError> {
+        if self.state.lock().unwrap().locked.is_some() {
+            return Err(HemliError::Locked);
+        }
+        Ok(())
+    }
+
+    /// Cache `secret`, unless the agent was locked while it was being read
+    /// or written.
+    fn remember(&self, key: (String, String), secret: StoredSecret) {
+        let mut state = self.state.lock().unwrap();
+        if state.locked.is_none()
+            && let Some(mut old) = state.cache.insert(key, secret)
+        {
+            old.wipe();
+        }
+    }
+
+    /// Wipe the cached secrets and any key the store holds.
+    fn lock(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.locked.is_none() {
+            let keys = state
+                .cache
+                .drain()
+                .map(|(key, mut secret)| {
+                    secret.wipe();
+                    key
+                })
+                .collect();
+            state.locked = Some(keys);
+        }
+        self.store.lock();
+    }
+
+    /// Unlock the store with `passphrase`, if it needs one, and load the
+    /// secrets cached before locking again.
+    fn unlock(&self, passphrase: Option<&str>) -> Result<(), HemliError> {
+        if let Some(passphrase) = passphrase {
+            self.store.unlock(passphrase)?;
+        }
+        let Some(keys) = self.state.lock().unwrap().locked.clone() else {
+            return Ok(());
+        };
+        let mut cache = HashMap::new();
+        for (namespace, name) in keys {
+            if let Some(secret) = self.store.get(&namespace, &name)? {
+                cache.insert((namespace, name), secret);
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        state.cache = cache;
+        state.locked = None;
+        state.last_used = Instant::now();
+        Ok(())
+    }
+
+    /// Lock if no request has come in for the idle timeout.
+    fn lock_if_idle(&self, now: Instant) {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        let idle = {
+            let state = self.state.lock().unwrap();
+            state.locked.is_none() && now.duration_since(state.last_used) >= idle_timeout
+        };
+        if idle {
+            debug!("agent locking after idle timeout");
+            self.lock();
+        }
+    }
+
+    /// Cached secrets with a source that clients have read since they were
+    /// last refreshed, and that are within their refresh-ahead window at
+    /// `now` but not yet expired. Secrets backing off after a failed refresh
+    /// are left out until `at` reaches their retry time.
+    fn due_for_refresh(&self, now: Timestamp, at: Instant) -> Vec<(String, String)> {
+        let state = self.state.lock().unwrap();
+        let mut due: Vec<_> = state
+            .cache
+            .iter()
+            .filter(|(key, secret)| {
+                secret.source_command.is_some()
+                    && in_refresh_window(secret, now)
+                    && state.read.contains(*key)
+                    && state
+                        .backoff
+                        .get(*key)
+                        .is_none_or(|(_, retry_at)| at >= *retry_at)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        due.sort();
+        due
+    }
+
+    /// Re-fetch due secrets, storing the new values through the cache. This
+    /// does not count as use of the agent. A secret whose refresh fails is
+    /// retried after a delay that doubles with each failure.
+    fn refresh_due(&self) {
+        let now = Timestamp::now();
+        let due = self.due_for_refresh(now, Instant::now());
+        if due.is_empty() {
+            return;
+        }
+        debug!(?due, "agent refreshing secrets");
+        let outcomes = refresh::refresh_secrets(
+            &Cached(self),
+            due,
+            |secret| in_refresh_window(secret, now),
+            REFRESH_JOBS,
+            self.default_source_timeout,
+        );
+        for (namespace, name, outcome) in outcomes {
+            self.record_refresh(namespace, name, &outcome, Instant::now());
+        }
+    }
+
+    /// Log the outcome of refreshing a secret and update its backoff.
+    fn record_refresh(
+        &self,
+        namespace: String,
+        name: String,
+        outcome: &RefreshOutcome,
+        at: Instant,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        match outcome {
+            RefreshOutcome::Refreshed => {
+                debug!(%namespace, %name, "agent refreshed secret");
+                let key = (namespace, name);
+                state.backoff.remove(&key);
+                state.read.remove(&key);
+            }
+            RefreshOutcome::Skipped(reason) => {
+                debug!(%namespace, %name, reason, "agent skipped refresh");
+            }
+            RefreshOutcome::Failed(e) => {
+                let failures = state
+                    .backoff
+                    .get(&(namespace.clone(), name.clone()))
+                    .map_or(1, |(failures, _)| failures.saturating_add(1));
+                let delay = REFRESH_INTERVAL
+                    .saturating_mul(1 << (failures - 1).min(16))
+                    .min(MAX_REFRESH_BACKOFF);
+                warn!(%namespace, %name, error = %e, retry_in = ?delay, "agent refresh failed");
+                state
+                    .backoff
+                    .insert((namespace, name), (failures, at + delay));
+            }
+        }
+    }
+}
+
+/// Whether `secret` is within its refresh-ahead window at `now` without
+/// having expired yet.
+fn in_refresh_window(secret: &StoredSecret, now: Timestamp) -> bool {
+    secret.is_due_for_refresh(now) && secret.expires_at.is_some_and(|exp| now < exp)
+}
+
+/// The agent's store as seen through its cache, refusing access while the
+/// agent is locked.
+struct Cached<'a>(&'a Agent);
+
+impl SecretStore for Cached<'_> {
+    fn get(&self, namespace: &str, name: &str) -> Result<Option<StoredSecret>, HemliError> {
+        self.0.ensure_unlocked()?;
+        let key = (namespace.to_string(), name.to_string());
+        if let Some(secret) = self.0.state.lock().unwrap().cache.get(&key) {
+            return Ok(Some(secret.clone()));
+        }
+        let secret = self.0.store.get(namespace, name)?;
+        if let Some(secret) = &secret {
+            self.0.remember(key, secret.clone());
+        }
+        Ok(secret)
+    }
+
+    fn set(&self, namespace: &str, name: &str, secret: &StoredSecret) -> Result<(), HemliError> {
+        self.0.ensure_unlocked()?;
+        self.0.store.set(namespace, name, secret)?;
+        self.0
+            .remember((namespace.to_string(), name.to_string()), secret.clone());
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, name: &str) -> Result<(), HemliError> {
+        self.0.ensure_unlocked()?;
+        self.0.store.delete(namespace, name)?;
+        let removed = self
+            .0
+            .state
+            .lock()
+            .unwrap()
+            .cache
+            .remove(&(namespace.to_string(), name.to_string()));
+        if let Some(mut secret) = removed {
+            secret.wipe();
+        }
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Option<Vec<(String, String)>>, HemliError> {
+        self.0.ensure_unlocked()?;
+        self.0.store.list()
+    }
+}
+
+/// Serve `store` on the agent socket for `backend` until stopped, locking
+/// after `idle_timeout` without a request.
+pub fn run(
+    store: Box<dyn SecretStore>,
+    backend: Backend,
+    default_source_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+) -> Result<(), HemliError> {
+    // Unlock the backend now, while there is a terminal to ask on; a client
+    // must never wait for the agent to prompt
+    store.authenticate()?;
+
+    let socket = socket_path(backend);
+    let listener = bind(&socket)?;
+    remove_socket_on_signal(&socket);
+    eprintln!("hemli agent listening on {}", socket.display());
+
+    let agent = Arc::new(Agent::new(store, default_source_timeout, idle_timeout));
+    // Separate threads, so that a slow source cannot hold up the idle lock
+    {
+        let agent = Arc::clone(&agent);
+        thread::spawn(move || {
+            loop {
+                thread::sleep(IDLE_CHECK_INTERVAL);
+                agent.lock_if_idle(Instant::now());
+            }
+        });
+    }
+    {
+        let agent = Arc::clone(&agent);
+        thread::spawn(move || {
+            loop {
+                thread::sleep(REFRESH_INTERVAL);
+                agent.refresh_due();
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!(error = %e, "agent failed to accept connection");
+                continue;
+            }
+        };
+        let agent = Arc::clone(&agent);
+        let socket = socket.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve_connection(&agent, stream, &socket) {
+                debug!(error = %e, "agent connection failed");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve_connection(agent: &Agent, stream: UnixStream, socket: &Path) -> Result<(), HemliError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    // Requests and answers may carry a passphrase or secret values
+    let mut line = Zeroizing::new(String::new());
+    while reader.read_line(&mut line)? > 0 {
+        let request: Result<Request, _> = serde_json::from_str(&line);
+        line.zeroize();
+        let stop = matches!(request, Ok(Request::Stop));
+        let response = match request {
+            Ok(request) => agent.handle(request),
+            Err(e) => Response::Error {
+                message: format!("invalid request: {e}"),
+            },
+        };
+        let mut answer = Zeroizing::new(serde_json::to_vec(&response)?);
+        answer.push(b'\n');
+        writer.write_all(&answer)?;
+        if stop {
+            let _ = fs::remove_file(socket);
+            std::process::exit(0);
+        }
+    }
+    Ok(())
+}
+
+/// Listen on `socket`, readable and writable only by the current user.
+/// A socket left behind by an agent that is no longer running is replaced.
+fn bind(socket: &Path) -> Result<UnixListener, HemliError> {
+    if let Some(dir) = socket.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+        // Nobody else can reach the socket in the moment before its own
+        // mode is set below
+        ensure_private_dir(dir)?;
+    }
+    if socket.exists() {
+        if is_listening(socket) {
+            return Err(HemliError::Agent(format!(
+                "an agent is already listening on {}",
+                socket.display()
+            )));
+        }
+        fs::remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// The socket for [`on_signal`] to remove.
+static SOCKET: OnceLock<CString> = OnceLock::new();
+
+/// Remove `socket` when the agent is interrupted, hung up on or terminated,
+/// so that clients stop trying to reach it.
+fn remove_socket_on_signal(socket: &Path) {
+    let Ok(path) = CString::new(socket.as_os_str().as_bytes()) else {
+        return;
+    };
+    if SOCKET.set(path).is_err() {
+        return;
+    }
+    let handler = on_signal as extern "C" fn(libc::c_int);
+    for signal in [libc::SIGINT, libc::SIGHUP, libc::SIGTERM] {
+        // SAFETY: `on_signal` only makes async-signal-safe calls.
+        unsafe {
+            libc::signal(signal, handler as libc::sighandler_t);
+        }
+    }
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    // SOCKET is set before the handler is installed and never changes, so
+    // reading it takes no lock
+    if let Some(path) = SOCKET.get() {
+        // SAFETY: unlink is async-signal-safe and `path` is a valid C string
+        // that lives as long as the process.
+        unsafe {
+            libc::unlink(path.as_ptr());
+        }
+    }
+    // SAFETY: signal and raise are async-signal-safe. With the default
+    // action restored, the signal ends the process as it would have without
+    // the handler.
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// Make sure `dir` is a directory (not a symlink) owned by the current user
+/// and accessible only to them, tightening its mode if needed.
+fn ensure_private_dir(dir: &Path) -> Result<(), HemliError> {
+    let refuse = |reason: &str| {
+        HemliError::Agent(format!("refusing to listen in {}: {reason}", dir.display()))
+    };
+    let meta = fs::symlink_metadata(dir)?;
+    if !meta.is_dir() {
+        return Err(refuse("not a directory"));
+    }
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    if meta.uid() != unsafe { libc::geteuid() } {
+        return Err(refuse("owned by another user"));
+    }
+    if meta.mode() & 0o077 != 0 {
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        if fs::symlink_metadata(dir)?.mode() & 0o077 != 0 {
+            return Err(refuse("accessible to other users"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::FileStore;
+
+    fn agent(dir: &Path) -> Agent {
+        let store = FileStore::new(dir.join("secrets.enc"));
+        store.unlock("hunter2").unwrap();
+        Agent::new(Box::new(store), None, Some(Duration::from_secs(60)))
+    }
+
+    fn get(agent: &Agent, name: &str) -> Option<StoredSecret> {
+        match agent.handle(Request::Get {
+            namespace: "ns".into(),
+            name: name.into(),
+        }) {
+            Response::Secret { secret } => secret.map(|secret| *secret),
+            other => panic!("expected a secret, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn caches_secrets_in_front_of_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent = agent(dir.path());
+        assert!(get(&agent, "sec").is_none());
+
+        let secret = StoredSecret::new("val".into(), None, None, None);
+        agent.handle(Request::Set {
+            namespace: "ns".into(),
+            name: "sec".into(),
+            secret: Box::new(secret),
+        });
+        assert_eq!(agent.store.get("ns", "sec").unwrap().unwrap().value, "val");
+
+        // Served from memory even once the backend has lost it
+        fs::remove_file(dir.path().join("secrets.enc")).unwrap();
+        assert_eq!(get(&agent, "sec").unwrap().value, "val");
+
+        agent.handle(Request::Delete {
+            namespace: "ns".into(),
+            name: "sec".into(),
+        });
+        assert!(get(&agent, "sec").is_none());
+    }
+
+    #[test]
+    fn finds_secrets_due_for_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent = agent(dir.path());
+        let mut due = StoredSecret::new("val".into(), Some("echo".into()), None, Some(60));
+        due.refresh_ahead_seconds = Some(120);
+        let unread = due.clone();
+        let mut expired = due.clone();
+        expired.expires_at = Some(Timestamp::now());
+        let fresh = StoredSecret::new("val".into(), Some("echo".into()), None, Some(60));
+        let manual = StoredSecret::new("val".into(), None, None, Some(60));
+        for (name, secret) in [
+            ("due", due),
+            ("unread", unread),
+            ("expired", expired),
+            ("fresh", fresh),
+            ("manual", manual),
+        ] {
+            agent.handle(Request::Set {
+                namespace: "ns".into(),
+                name: name.into(),
+                secret: Box::new(secret),
+            });
+            if name != "unread" {
+                get(&agent, name);
+            }
+        }
+
+        let later = Timestamp::now()
+            .checked_add(jiff::SignedDuration::from_secs(30))
+            .unwrap();
+        let key = ("ns".to_string(), "due".to_string());
+        let at = Instant::now();
+        assert_eq!(agent.due_for_refresh(later, at), vec![key.clone()]);
+
+        // Failures back off, doubling each time
+        let failed = RefreshOutcome::Failed(anyhow::anyhow!("source failed"));
+        agent.record_refresh(key.0.clone(), key.1.clone(), &failed, at);
+        assert!(agent.due_for_refresh(later, at).is_empty());
+        assert_eq!(
+            agent.due_for_refresh(later, at + REFRESH_INTERVAL),
+            vec![key.clone()]
+        );
+        agent.record_refresh(key.0.clone(), key.1.clone(), &failed, at);
+        assert!(
+            agent
+                .due_for_refresh(later, at + REFRESH_INTERVAL)
+                .is_empty()
+        );
+        assert_eq!(
+            agent.due_for_refresh(later, at + 2 * REFRESH_INTERVAL),
+            vec![key.clone()]
+        );
+
+        // A refresh clears the backoff, and waits for the next read
+        agent.record_refresh(key.0.clone(), key.1.clone(), &RefreshOutcome::Refreshed, at);
+        assert!(agent.due_for_refresh(later, at).is_empty());
+        get(&agent, "due");
+        assert_eq!(agent.due_for_refresh(later, at), vec![key]);
+    }
+
+    #[test]
+    fn lock_wipes_the_cache_until_unlocked() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent = agent(dir.path());
+        agent.handle(Request::Set {
+            namespace: "ns".into(),
+            name: "sec".into(),
+            secret: Box::new(StoredSecret::new("val".into(), None, None, None)),
+        });
+
+        agent.handle(Request::Lock);
+        assert!(agent.state.lock().unwrap().cache.is_empty());
+        let locked = agent.handle(Request::Get {
+            namespace: "ns".into(),
+            name: "sec".into(),
+        });
+        assert!(
+            matches!(&locked, Response::Error { message } if message.contains("locked")),
+            "{locked:?}"
+        );
+
+        let wrong = agent.handle(Request::Unlock {
+            passphrase: Some(Zeroizing::new("wrong".into())),
+        });
+        assert!(matches!(wrong, Response::Error { .. }), "{wrong:?}");
+        assert!(agent.state.lock().unwrap().locked.is_some());
+
+        let unlocked = agent.handle(Request::Unlock {
+            passphrase: Some(Zeroizing::new("hunter2".into())),
+        });
+        assert!(matches!(unlocked, Response::Done), "{unlocked:?}");
+        // Loaded again before anyone asks
+        assert!(
+            agent
+                .state
+                .lock()
+                .unwrap()
+                .cache
+                .contains_key(&("ns".into(), "sec".into()))
+        );
+        assert_eq!(get(&agent, "sec").unwrap().value, "val");
+    }
+
+    #[test]
+    fn locks_when_idle() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent = agent(dir.path());
+        assert!(get(&agent, "sec").is_none());
+
+        let used = agent.state.lock().unwrap().last_used;
+        agent.lock_if_idle(used + Duration::from_secs(59));
+        assert!(agent.state.lock().unwrap().locked.is_none());
+        agent.lock_if_idle(used + Duration::from_secs(60));
+        assert!(agent.state.lock().unwrap().locked.is_some());
+    }
+
+    #[test]
+    fn own_refreshes_do_not_count_as_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent = agent(dir.path());
+        let used = agent.state.lock().unwrap().last_used;
+
+        let secret = StoredSecret::new("val".into(), None, None, None);
+        Cached(&agent).set("ns", "sec", &secret).unwrap();
+        assert_eq!(
+            Cached(&agent).get("ns", "sec").unwrap().unwrap().value,
+            "val"
+        );
+        assert_eq!(agent.state.lock().unwrap().last_used, used);
+
+        // ...and a client request still does
+        get(&agent, "sec");
+        assert!(agent.state.lock().unwrap().last_used > used);
+
+        // Locked, the agent's own view is closed too
+        agent.handle(Request::Lock);
+        assert!(matches!(
+            Cached(&agent).get("ns", "sec"),
+            Err(HemliError::Locked)
+        ));
+    }
+
+    #[test]
+    fn socket_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("run").join("agent.sock");
+        let listener = bind(&socket).unwrap();
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let agent = Arc::new(agent(dir.path()));
+        {
+            let agent = Arc::clone(&agent);
+            let socket = socket.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    serve_connection(&agent, stream.unwrap(), &socket).unwrap();
+                }
+            });
+        }
+
+        match request(&socket, &Request::Status).unwrap() {
+            Some(Response::Status {
+                pid,
+                cached,
+                locked,
+            }) => {
+                assert_eq!(pid, std::process::id());
+                assert_eq!(cached, 0);
+                assert!(!locked);
+            }
+            other => panic!("expected status, got {other:?}"),
+        }
+        // A second agent cannot take over a live socket
+        assert!(is_listening(&socket));
+        assert!(matches!(bind(&socket), Err(HemliError::Agent(_))));
+
+        // No agent at all is not an error
+        let missing = dir.path().join("missing.sock");
+        assert!(request(&missing, &Request::Status).unwrap().is_none());
+    }
+
+    #[test]
+    fn socket_left_behind_is_not_listening() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("run").join("agent.sock");
+        drop(bind(&socket).unwrap());
+        assert!(socket.exists());
+        assert!(!is_listening(&socket));
+        // ...and a new agent takes its place
+        let _listener = bind(&socket).unwrap();
+        assert!(is_listening(&socket));
+    }
+
+    #[test]
+    fn request_gives_up_on_an_agent_that_does_not_answer() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("run").join("agent.sock");
+        // Bound but never accepting, like an agent stuck on a prompt
+        let _listener = bind(&socket).unwrap();
+
+        let start = Instant::now();
+        let result = request_within(&socket, &Request::Status, Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(
+            matches!(&result, Err(HemliError::Agent(message)) if message.contains("no answer")),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn binds_only_in_a_private_directory() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // An existing directory open to others is tightened first
+        let shared = dir.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o755)).unwrap();
+        bind(&shared.join("agent.sock")).unwrap();
+        let mode = fs::metadata(&shared).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // A symlink could point anywhere
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&shared, &link).unwrap();
+        assert!(matches!(
+            bind(&link.join("agent.sock")),
+            Err(HemliError::Agent(_))
+        ));
+    }
+}
@@ -1,6 +1,24 @@
+use std::process;
+use std::process::Stdio;
+use std::time::Duration;
+
+use clap::ArgGroup;
 use clap::Parser;
 use clap::Subcommand;
+use clap::ValueEnum;
 use clap_complete::Shell;
+use jiff::SignedDuration;
+use jiff::Timestamp;
+use jiff::Zoned;
+use jiff::civil;
+use jiff::tz::TimeZone;
+
+use crate::duration;
+use crate::listing::ListFormat;
+use crate::model::DailyExpiry;
+use crate::model::OutputMode;
+use crate::model::TtlFrom;
+use crate::store::Backend;
 
 /// Secret management CLI for local development
 ///
@@ -10,6 +28,27 @@ use clap_complete::Shell;
 #[derive(Debug, Parser)]
 #[command(name = "hemli")]
 pub struct Cli {
+    /// Storage backend for cached secrets
+    ///
+    /// Selects where secret values and their metadata are persisted. The
+    /// index of known secrets is always kept in the local data directory.
+    #[arg(long, global = true, env = "HEMLI_BACKEND", default_value = "keyring")]
+    pub backend: Backend,
+
+    /// Time limit for source commands without their own --source-timeout
+    ///
+    /// A source command that runs longer is killed, together with any
+    /// processes it started, and the fetch fails. By default source commands
+    /// may run indefinitely.
+    #[arg(
+        long,
+        global = true,
+        env = "HEMLI_SOURCE_TIMEOUT",
+        value_name = "DURATION",
+        value_parser = duration::parse_arg
+    )]
+    pub default_source_timeout: Option<SignedDuration>,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -25,14 +64,18 @@ pub enum Command {
     /// When a secret is stored with a source command, subsequent calls will
     /// automatically re-fetch using that stored source when the TTL expires --
     /// no need to pass --source-sh/--source-cmd again.
+    ///
+    /// If neither the command line nor the cached secret provide a source, the
+    /// nearest hemli.toml manifest in the current directory or its parents is
+    /// consulted for the namespace, source, and TTL.
     Get {
         /// Namespace for the secret
         ///
         /// Groups secrets by project or environment. The keyring service name
         /// is "hemli:<namespace>", so secrets in different namespaces are fully
-        /// isolated.
+        /// isolated. Defaults to the namespace declared in hemli.toml.
         #[arg(short, long, env = "HEMLI_NAMESPACE")]
-        namespace: String,
+        namespace: Option<String>,
 
         /// Name of the secret
         ///
@@ -61,14 +104,14 @@ pub enum Command {
         #[arg(long, env = "HEMLI_NO_STORE")]
         no_store: bool,
 
-        /// TTL in seconds for the cached secret
+        /// How long the cached secret stays valid (e.g. 30m, 12h, 7d, PT1H)
         ///
-        /// Sets how long the cached secret is considered valid. After this
-        /// duration, the next get call will re-fetch from the source. If
-        /// omitted, falls back to the TTL stored with the existing cached
-        /// secret, or no expiration if none was ever set.
-        #[arg(long)]
-        ttl: Option<i64>,
+        /// After this duration, the next get call will re-fetch from the
+        /// source. A bare number is seconds. If omitted, falls back to the TTL
+        /// stored with the existing cached secret, or no expiration if none
+        /// was ever set.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse_arg)]
+        ttl: Option<SignedDuration>,
 
         /// Source command to run via sh -c
         ///
@@ -80,11 +123,197 @@ pub enum Command {
 
         /// Source command to run directly
         ///
-        /// The command string is split on whitespace and executed directly
-        /// without a shell. Use this when you don't need shell features.
-        /// Mutually exclusive with --source-sh.
-        #[arg(long, conflicts_with = "source_sh")]
-        source_cmd: Option<String>,
+        /// Runs the command without a shell. Give each argument its own
+        /// --source-cmd, or end the options with `--source-cmd --` and give
+        /// the command after it:
+        ///
+        ///   hemli get -n myapp key --source-cmd -- op read "op://Dev Vault/key"
+        ///
+        /// A single --source-cmd value is split on whitespace. Mutually
+        /// exclusive with --source-sh.
+        #[arg(long, value_name = "ARG", num_args = 0..=1, conflicts_with = "source_sh")]
+        source_cmd: Option<Vec<String>>,
+
+        /// Source command and arguments, after `--source-cmd --`
+        #[arg(last = true, value_name = "ARGV", requires = "source_cmd")]
+        source_argv: Vec<String>,
+
+        /// Time limit for the source command (e.g. 30s, 2m)
+        ///
+        /// Kills the source command if it runs longer than this, together
+        /// with any processes it started unless it runs on a terminal. Stored
+        /// with the secret and used for later automatic refreshes. Overrides
+        /// --default-source-timeout.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse_arg)]
+        source_timeout: Option<SignedDuration>,
+
+        /// Retry a failing source command up to N more times
+        ///
+        /// Attempts that fail or time out are retried with exponential
+        /// backoff. Stored with the secret and used for later automatic
+        /// refreshes.
+        #[arg(long, value_name = "N")]
+        retries: Option<u32>,
+
+        /// Delay before the first retry, doubled for each retry after it
+        ///
+        /// Defaults to 1s. Delays are capped at one minute.
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = duration::parse_arg,
+            requires = "retries"
+        )]
+        retry_backoff: Option<SignedDuration>,
+
+        /// Return the cached value if fetching a fresh one fails
+        ///
+        /// When the cached secret has expired (or --force-refresh is given)
+        /// and the source command fails, prints a warning and returns the old
+        /// value instead of an error. With MAX_AGE (e.g. 1d), values that
+        /// expired longer ago than that are not served. Stored with the
+        /// secret.
+        #[arg(
+            long,
+            value_name = "MAX_AGE",
+            num_args = 0..=1,
+            require_equals = true,
+            value_parser = duration::parse_arg
+        )]
+        stale_if_error: Option<Option<SignedDuration>>,
+
+        /// How the source command's output becomes the secret value
+        ///
+        /// "trim" (the default) strips surrounding whitespace. "raw" keeps the
+        /// output byte for byte, such as PEM keys with trailing newlines or
+        /// binary keystores. "base64" stores the exact output base64-encoded.
+        /// Stored with the secret and used for later refreshes.
+        #[arg(long, value_name = "MODE")]
+        output_mode: Option<OutputMode>,
+
+        /// Use one field of the source command's JSON output as the value
+        ///
+        /// PATH is a JSON pointer such as /data/data/password, or a dotted
+        /// path such as data.data.password where numbers index into arrays.
+        /// Strings are used as they are; other values as JSON. Fails if the
+        /// output is not JSON or has no such field. Stored with the secret.
+        #[arg(long, value_name = "PATH")]
+        field: Option<String>,
+
+        /// Also extract a named field from the JSON output, as NAME[=PATH]
+        ///
+        /// May be repeated. PATH is a JSON pointer or dotted path as for
+        /// --field, and defaults to NAME. All fields come from the same run
+        /// of the source command and share the secret's TTL. Read them back
+        /// as SECRET#NAME:
+        ///
+        ///   hemli get -n myapp db --fields user=data.username --fields password=data.password ...
+        ///   hemli get -n myapp db#password
+        #[arg(long, value_name = "NAME[=PATH]", value_parser = parse_field_mapping)]
+        fields: Vec<(String, String)>,
+
+        /// Expire the cached secret when the fetched secret itself says so
+        ///
+        /// "jwt" reads the `exp` claim of a JWT value. "json:PATH" reads a
+        /// field of the JSON source output (PATH as for --field): a number is
+        /// seconds from the fetch, such as Vault's lease_duration, unless it
+        /// is 1000000000 or more, which is a Unix timestamp, such as an OAuth
+        /// expires_at. A string is a timestamp, such as an AWS credential
+        /// Expiration. If --ttl is also set, whichever expires first wins.
+        /// If the expiry cannot be read, a warning is printed and only --ttl
+        /// applies. Stored with the secret.
+        #[arg(long, value_name = "SOURCE")]
+        ttl_from: Option<TtlFrom>,
+
+        /// Expire this long before the expiry read with --ttl-from (e.g. 30s)
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = duration::parse_arg,
+            requires = "ttl_from"
+        )]
+        ttl_margin: Option<SignedDuration>,
+
+        /// Refresh in the background this long before expiry (e.g. 5m)
+        ///
+        /// A get within this window before the cached secret expires returns
+        /// the cached value at once and starts a detached `hemli refresh` of
+        /// the secret, so the next caller finds a fresh value instead of
+        /// waiting for the source command. Stored with the secret.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse_arg)]
+        refresh_ahead: Option<SignedDuration>,
+
+        /// Expire the cached secret at a fixed time
+        ///
+        /// TIMESTAMP is RFC 3339 like 2025-01-15T17:00:00Z, or a local date
+        /// and time like 2025-01-15T17:00, and must be in the future. The
+        /// first fetch after it has passed drops it, leaving the other
+        /// expiries. Stored with the secret; the earliest of all expiries
+        /// applies.
+        #[arg(long, value_name = "TIMESTAMP", value_parser = parse_future_timestamp)]
+        expires_at: Option<Timestamp>,
+
+        /// Expire the cached secret every day at a time of day
+        ///
+        /// Given as HH:MM in the system time zone, or HH:MM[TZ] with an IANA
+        /// time zone such as 03:00[UTC] or 18:00[Europe/Oslo]. A fetched
+        /// secret expires at the next such time. Stored with the secret; the
+        /// earliest of all expiries applies.
+        #[arg(long, value_name = "HH:MM[TZ]")]
+        expires_daily_at: Option<DailyExpiry>,
+    },
+
+    /// Run a command with secrets injected as environment variables
+    ///
+    /// Resolves each --env mapping exactly like `hemli get` (using the cache
+    /// and re-fetching expired secrets from their stored source), then runs
+    /// the command with the resulting variables set. Secret values are never
+    /// printed.
+    ///
+    ///   hemli run -n myapp --env DB_PASSWORD=db_password -- cargo run
+    Run {
+        /// Namespace for the secrets
+        ///
+        /// Defaults to the namespace declared in hemli.toml.
+        #[arg(short, long, env = "HEMLI_NAMESPACE")]
+        namespace: Option<String>,
+
+        /// Environment variable to set from a secret, as VAR=SECRET
+        ///
+        /// May be repeated. Each secret must already be cached or have a
+        /// stored source command.
+        #[arg(short, long = "env", value_name = "VAR=SECRET", value_parser = parse_env_mapping)]
+        env: Vec<(String, String)>,
+
+        /// Command to run, followed by its arguments
+        #[arg(required = true, trailing_var_arg = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+
+    /// Store a secret value directly, without a source command
+    ///
+    /// Reads the value from stdin when it is piped, or prompts for it without
+    /// echoing when run in a terminal. A single trailing newline is stripped
+    /// from piped input; anything else, including binary data, is stored as
+    /// is. A new secret has no source, so once it expires it must be set
+    /// again (or given a source with `hemli edit`). An existing secret keeps
+    /// its source and settings, and only its value is replaced.
+    ///
+    ///   pass show myapp/db | hemli set -n myapp db_password
+    Set {
+        /// Namespace for the secret
+        #[arg(short, long, env = "HEMLI_NAMESPACE")]
+        namespace: String,
+
+        /// Name of the secret
+        secret: String,
+
+        /// How long the stored secret stays valid (e.g. 30m, 12h, 7d)
+        ///
+        /// If omitted, a new secret never expires and an existing one keeps
+        /// its TTL.
+        #[arg(long, value_name = "DURATION", value_parser = duration::parse_arg)]
+        ttl: Option<SignedDuration>,
     },
 
     /// Delete a secret from the keyring
@@ -102,9 +331,10 @@ pub enum Command {
 
     /// List stored secrets
     ///
-    /// Prints all cached secrets from the index as tab-separated lines:
-    /// namespace, secret name, and creation timestamp. Use -n to filter
-    /// by namespace.
+    /// Prints all cached secrets from the index. By default each secret is a
+    /// tab-separated line of namespace, secret name, creation timestamp,
+    /// status (valid or expired), and TTL (empty if none). Use -n to filter by
+    /// namespace and --format for other layouts.
     List {
         /// Filter by namespace
         ///
@@ -112,6 +342,31 @@ pub enum Command {
         /// namespaces are shown.
         #[arg(short, long, env = "HEMLI_NAMESPACE")]
         namespace: Option<String>,
+
+        /// Output format: tsv, table, json, or a template
+        ///
+        /// "json" includes TTL, expiry, and source type for each secret.
+        /// Anything containing braces is a per-line template, e.g.
+        /// '{namespace}/{secret}'. Available placeholders: namespace, secret,
+        /// created_at, ttl (such as 12h), ttl_seconds, expires_at, status,
+        /// source_type. Use '{{' and '}}' for literal braces.
+        #[arg(long, default_value = "tsv")]
+        format: ListFormat,
+
+        /// Only show secrets whose TTL has expired
+        ///
+        /// These are the secrets that will be re-fetched from their source on
+        /// the next get.
+        #[arg(long, group = "expiry")]
+        expired: bool,
+
+        /// Only show secrets that have not expired
+        #[arg(long, group = "expiry")]
+        valid: bool,
+
+        /// Only show valid secrets that expire within DURATION (e.g. 1h, 30m)
+        #[arg(long, group = "expiry", value_name = "DURATION", value_parser = duration::parse_arg)]
+        expiring_within: Option<SignedDuration>,
     },
 
     /// Inspect a cached secret, showing full metadata as JSON
@@ -141,13 +396,18 @@ pub enum Command {
         /// Name of the secret
         secret: String,
 
-        /// New TTL in seconds
+        /// New TTL (e.g. 30m, 12h, 7d)
         ///
         /// Replaces the existing TTL and recalculates the expiration time
         /// from the original creation timestamp. Mutually exclusive with
         /// --clear-ttl.
-        #[arg(long, conflicts_with = "clear_ttl")]
-        ttl: Option<i64>,
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = duration::parse_arg,
+            conflicts_with = "clear_ttl"
+        )]
+        ttl: Option<SignedDuration>,
 
         /// Remove TTL (secret will never expire)
         ///
@@ -166,11 +426,311 @@ pub enum Command {
         /// New source command (direct)
         ///
         /// Replaces the stored source command and sets the source type to
-        /// "cmd". Mutually exclusive with --source-sh.
-        #[arg(long, conflicts_with = "source_sh")]
-        source_cmd: Option<String>,
+        /// "cmd". Arguments are given as for `hemli get`: repeat
+        /// --source-cmd, or put the command after `--source-cmd --`.
+        /// Mutually exclusive with --source-sh.
+        #[arg(long, value_name = "ARG", num_args = 0..=1, conflicts_with = "source_sh")]
+        source_cmd: Option<Vec<String>>,
+
+        /// New source command and arguments, after `--source-cmd --`
+        #[arg(last = true, value_name = "ARGV", requires = "source_cmd")]
+        source_argv: Vec<String>,
+
+        /// New time limit for the source command (e.g. 30s, 2m)
+        ///
+        /// Mutually exclusive with --clear-source-timeout.
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = duration::parse_arg,
+            conflicts_with = "clear_source_timeout"
+        )]
+        source_timeout: Option<SignedDuration>,
+
+        /// Remove the source command's own time limit
+        ///
+        /// The secret falls back to --default-source-timeout, if any.
+        /// Mutually exclusive with --source-timeout.
+        #[arg(long, conflicts_with = "source_timeout")]
+        clear_source_timeout: bool,
+
+        /// New number of retries for a failing source command
+        ///
+        /// Mutually exclusive with --clear-retries.
+        #[arg(long, value_name = "N", conflicts_with = "clear_retries")]
+        retries: Option<u32>,
+
+        /// New delay before the first retry (defaults to 1s)
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = duration::parse_arg,
+            requires = "retries"
+        )]
+        retry_backoff: Option<SignedDuration>,
+
+        /// Stop retrying the source command when it fails
+        ///
+        /// Mutually exclusive with --retries.
+        #[arg(long, conflicts_with = "retries")]
+        clear_retries: bool,
+
+        /// Return the cached value if fetching a fresh one fails
+        ///
+        /// With MAX_AGE, only values that expired less than MAX_AGE ago are
+        /// served. Mutually exclusive with --clear-stale-if-error.
+        #[arg(
+            long,
+            value_name = "MAX_AGE",
+            num_args = 0..=1,
+            require_equals = true,
+            value_parser = duration::parse_arg,
+            conflicts_with = "clear_stale_if_error"
+        )]
+        stale_if_error: Option<Option<SignedDuration>>,
+
+        /// Fail again when fetching a fresh value fails
+        ///
+        /// Mutually exclusive with --stale-if-error.
+        #[arg(long, conflicts_with = "stale_if_error")]
+        clear_stale_if_error: bool,
+
+        /// New output mode, used from the next fetch on
+        #[arg(long, value_name = "MODE")]
+        output_mode: Option<OutputMode>,
+
+        /// New JSON field to extract, used from the next fetch on
+        ///
+        /// Mutually exclusive with --clear-field.
+        #[arg(long, value_name = "PATH", conflicts_with = "clear_field")]
+        field: Option<String>,
+
+        /// Use the whole source output as the value again
+        ///
+        /// Mutually exclusive with --field.
+        #[arg(long, conflicts_with = "field")]
+        clear_field: bool,
+
+        /// Replace the named fields, as NAME[=PATH]
+        ///
+        /// May be repeated. The new fields are filled in by the next fetch.
+        /// Mutually exclusive with --clear-fields.
+        #[arg(
+            long,
+            value_name = "NAME[=PATH]",
+            value_parser = parse_field_mapping,
+            conflicts_with = "clear_fields"
+        )]
+        fields: Vec<(String, String)>,
+
+        /// Remove all named fields
+        ///
+        /// Mutually exclusive with --fields.
+        #[arg(long, conflicts_with = "fields")]
+        clear_fields: bool,
+
+        /// Read the expiry from the fetched secret, as jwt or json:PATH
+        ///
+        /// Used from the next fetch on. Mutually exclusive with
+        /// --clear-ttl-from.
+        #[arg(long, value_name = "SOURCE", conflicts_with = "clear_ttl_from")]
+        ttl_from: Option<TtlFrom>,
+
+        /// New margin before the expiry read with --ttl-from, applied at once
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = duration::parse_arg,
+            conflicts_with = "clear_ttl_from"
+        )]
+        ttl_margin: Option<SignedDuration>,
+
+        /// Stop reading the expiry from the fetched secret
+        ///
+        /// Clears --ttl-from and --ttl-margin; only the TTL applies again.
+        #[arg(long)]
+        clear_ttl_from: bool,
+
+        /// New background refresh window before expiry
+        ///
+        /// Mutually exclusive with --clear-refresh-ahead.
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = duration::parse_arg,
+            conflicts_with = "clear_refresh_ahead"
+        )]
+        refresh_ahead: Option<SignedDuration>,
+
+        /// Only refresh the secret once it has expired
+        ///
+        /// Mutually exclusive with --refresh-ahead.
+        #[arg(long, conflicts_with = "refresh_ahead")]
+        clear_refresh_ahead: bool,
+
+        /// New fixed expiry time
+        ///
+        /// Unlike with get, the time may have passed already, which expires
+        /// the cached secret at once; the next fetch then drops it. Mutually
+        /// exclusive with --clear-expires-at.
+        #[arg(
+            long,
+            value_name = "TIMESTAMP",
+            value_parser = parse_timestamp,
+            conflicts_with = "clear_expires_at"
+        )]
+        expires_at: Option<Timestamp>,
+
+        /// Remove the fixed expiry time
+        ///
+        /// Mutually exclusive with --expires-at.
+        #[arg(long, conflicts_with = "expires_at")]
+        clear_expires_at: bool,
+
+        /// New daily expiry time, as HH:MM or HH:MM[TZ]
+        ///
+        /// Mutually exclusive with --clear-expires-daily-at.
+        #[arg(
+            long,
+            value_name = "HH:MM[TZ]",
+            conflicts_with = "clear_expires_daily_at"
+        )]
+        expires_daily_at: Option<DailyExpiry>,
+
+        /// Remove the daily expiry time
+        ///
+        /// Mutually exclusive with --expires-daily-at.
+        #[arg(long, conflicts_with = "expires_daily_at")]
+        clear_expires_daily_at: bool,
     },
 
+    /// Re-fetch cached secrets from their stored sources
+    ///
+    /// Walks the index and re-runs the stored source command of each
+    /// matching secret, several at a time, storing the new values. By
+    /// default only expired secrets, and secrets within their --refresh-ahead
+    /// window, are refreshed. Prints a per-secret result and a summary to
+    /// stderr, and exits with an error if any refresh failed. Secrets without
+    /// a stored source, and secrets another hemli process is fetching right
+    /// now, are skipped.
+    Refresh {
+        /// Only refresh secrets in this namespace
+        #[arg(short, long, env = "HEMLI_NAMESPACE")]
+        namespace: Option<String>,
+
+        /// Only refresh these secrets
+        #[arg(value_name = "SECRET")]
+        secrets: Vec<String>,
+
+        /// Refresh every secret, whether or not it has expired
+        #[arg(long, conflicts_with = "expired")]
+        all: bool,
+
+        /// Refresh only secrets that have expired, leaving those still within
+        /// their --refresh-ahead window
+        #[arg(long, conflicts_with = "all")]
+        expired: bool,
+
+        /// Number of source commands to run in parallel
+        #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+    },
+
+    /// Delete all secrets matching the given criteria
+    ///
+    /// Removes matching secrets from both the backend and the index in one
+    /// operation. At least one criterion is required; when several are given
+    /// a secret must match all of them. Expiry and age are taken from the
+    /// index.
+    ///
+    ///   hemli purge -n old-project
+    ///   hemli purge --expired --older-than 30d --dry-run
+    #[command(group(ArgGroup::new("criteria").required(true).multiple(true)))]
+    Purge {
+        /// Purge secrets in this namespace
+        ///
+        /// Unlike other commands this is not read from HEMLI_NAMESPACE, so a
+        /// bare `hemli purge` never deletes a whole namespace by accident.
+        #[arg(short, long, group = "criteria")]
+        namespace: Option<String>,
+
+        /// Purge secrets whose TTL has expired
+        #[arg(long, group = "criteria")]
+        expired: bool,
+
+        /// Purge secrets fetched longer than DURATION ago (e.g. 30d, 12h)
+        #[arg(long, group = "criteria", value_name = "DURATION", value_parser = duration::parse_arg)]
+        older_than: Option<SignedDuration>,
+
+        /// List what would be purged without deleting anything
+        ///
+        /// Prints one tab-separated namespace and secret name per line.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Check the index against the storage backend
+    ///
+    /// Reports secrets that are indexed but missing from the backend, stored
+    /// payloads that cannot be read or parsed, and out-of-date index metadata.
+    /// Backends that can enumerate their contents (such as the file backend)
+    /// are also checked for secrets missing from the index. Exits with an
+    /// error if any problems remain.
+    Doctor {
+        /// Repair the problems found
+        ///
+        /// Drops orphaned index entries, and adds or updates index entries
+        /// from the backend. Payloads that cannot be read or parsed are only
+        /// reported, never deleted.
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Run an agent that keeps stored secrets in memory
+    ///
+    /// Serves get, set and delete for the selected backend over a Unix
+    /// socket (mode 0600) in the user's runtime directory, so the keyring is
+    /// not asked again and the file backend's passphrase is entered once.
+    /// Other hemli commands use the agent while it runs and access the
+    /// backend directly otherwise. The agent refreshes secrets that clients
+    /// read and that are within their refresh-ahead window on its own. Runs in
+    /// the foreground; start it in the background with your session, e.g.
+    /// `hemli agent &`.
+    Agent {
+        /// Show whether an agent is running and how many secrets it holds
+        #[arg(long)]
+        status: bool,
+
+        /// Stop the running agent
+        #[arg(long, conflicts_with = "status")]
+        stop: bool,
+
+        /// Lock the agent after this long without a request (e.g. 15m)
+        ///
+        /// File backend only.
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = duration::parse_arg,
+            conflicts_with_all = ["status", "stop"]
+        )]
+        idle_timeout: Option<SignedDuration>,
+    },
+
+    /// Lock the running agent
+    ///
+    /// Wipes the secrets the agent holds in memory, and the file backend's
+    /// key, until `hemli unlock`. Requests to a locked agent fail. Only an
+    /// agent for the file backend can be locked.
+    Lock,
+
+    /// Unlock the running agent
+    ///
+    /// Authenticates again with the file backend's passphrase, from
+    /// HEMLI_PASSPHRASE or the terminal, and loads the secrets the agent held
+    /// before it was locked back into memory.
+    Unlock,
+
     /// Generate shell completion scripts
     ///
     /// Prints a completion script for the given shell to stdout. Source the
@@ -183,6 +743,88 @@ pub enum Command {
     },
 }
 
+/// The argument vector for `--source-cmd` values followed by the arguments
+/// after `--`, or `None` if `--source-cmd` was not given. A lone value is split
+/// on whitespace, as all values were before arguments could be repeated.
+pub fn source_cmd_argv(values: Option<Vec<String>>, trailing: Vec<String>) -> Option<Vec<String>> {
+    let mut argv = values?;
+    if argv.len() == 1 && trailing.is_empty() {
+        argv = argv[0].split_whitespace().map(String::from).collect();
+    }
+    argv.extend(trailing);
+    Some(argv)
+}
+
+/// A `hemli refresh` of `secrets` in `namespace`, run by this same executable
+/// with the given global options. Its output is discarded.
+pub fn refresh_command(
+    backend: Backend,
+    default_source_timeout: Option<Duration>,
+    namespace: &str,
+    secrets: &[String],
+) -> std::io::Result<process::Command> {
+    let mut command = process::Command::new(std::env::current_exe()?);
+    if let Some(name) = backend.to_possible_value() {
+        command.args(["--backend", name.get_name()]);
+    }
+    if let Some(timeout) = default_source_timeout {
+        command.arg(format!("--default-source-timeout={}s", timeout.as_secs()));
+    }
+    command
+        .args(["refresh", "--namespace", namespace, "--"])
+        .args(secrets)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    Ok(command)
+}
+
+/// Parse an RFC 3339 timestamp, a zoned date and time such as
+/// `2025-01-15T17:00[Europe/Oslo]`, or a civil date and time in the system
+/// time zone.
+fn parse_timestamp(s: &str) -> Result<Timestamp, String> {
+    if let Ok(timestamp) = s.parse::<Timestamp>() {
+        Ok(timestamp)
+    } else if let Ok(zoned) = s.parse::<Zoned>() {
+        Ok(zoned.timestamp())
+    } else {
+        s.parse::<civil::DateTime>()
+            .and_then(|dt| dt.to_zoned(TimeZone::system()))
+            .map(|zoned| zoned.timestamp())
+            .map_err(|_| {
+                format!(
+                    "expected a timestamp like 2025-01-15T17:00:00Z or 2025-01-15T17:00, got '{s}'"
+                )
+            })
+    }
+}
+
+/// Like [`parse_timestamp`], but the time must be in the future.
+fn parse_future_timestamp(s: &str) -> Result<Timestamp, String> {
+    let timestamp = parse_timestamp(s)?;
+    if timestamp <= Timestamp::now() {
+        return Err(format!("'{s}' is in the past"));
+    }
+    Ok(timestamp)
+}
+
+fn parse_field_mapping(s: &str) -> Result<(String, String), String> {
+    let (name, path) = s.split_once('=').unwrap_or((s, s));
+    if name.is_empty() || path.is_empty() || name.contains('#') {
+        return Err(format!("expected NAME or NAME=PATH, got '{s}'"));
+    }
+    Ok((name.to_string(), path.to_string()))
+}
+
+fn parse_env_mapping(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((var, secret)) if !var.is_empty() && !secret.is_empty() => {
+            Ok((var.to_string(), secret.to_string()))
+        }
+        _ => Err(format!("expected VAR=SECRET, got '{s}'")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -194,7 +836,7 @@ mod tests {
             Command::Get {
                 namespace, secret, ..
             } => {
-                assert_eq!(namespace, "myns");
+                assert_eq!(namespace.as_deref(), Some("myns"));
                 assert_eq!(secret, "mysecret");
             }
             _ => panic!("expected Get"),
@@ -227,13 +869,66 @@ mod tests {
         ])
         .unwrap();
         match cli.command {
-            Command::Get { source_cmd, .. } => {
-                assert_eq!(source_cmd.as_deref(), Some("my-cmd arg1"));
+            Command::Get {
+                source_cmd,
+                source_argv,
+                ..
+            } => {
+                assert_eq!(source_cmd, Some(vec!["my-cmd arg1".to_string()]));
+                assert_eq!(
+                    source_cmd_argv(source_cmd, source_argv),
+                    Some(vec!["my-cmd".to_string(), "arg1".to_string()])
+                );
             }
             _ => panic!("expected Get"),
         }
     }
 
+    fn get_source_argv(args: &[&str]) -> Option<Vec<String>> {
+        let cli =
+            Cli::try_parse_from(["hemli", "get", "-n", "ns", "sec"].iter().chain(args)).unwrap();
+        match cli.command {
+            Command::Get {
+                source_cmd,
+                source_argv,
+                ..
+            } => source_cmd_argv(source_cmd, source_argv),
+            _ => panic!("expected Get"),
+        }
+    }
+
+    #[test]
+    fn parse_get_with_repeated_source_cmd() {
+        let argv = get_source_argv(&[
+            "--source-cmd",
+            "op",
+            "--source-cmd",
+            "read",
+            "--source-cmd",
+            "op://Dev Vault/db",
+        ]);
+        assert_eq!(
+            argv,
+            Some(vec!["op".into(), "read".into(), "op://Dev Vault/db".into()])
+        );
+    }
+
+    #[test]
+    fn parse_get_with_source_cmd_after_separator() {
+        let argv = get_source_argv(&["--source-cmd", "--", "op", "read", "op://Dev Vault/db"]);
+        assert_eq!(
+            argv,
+            Some(vec!["op".into(), "read".into(), "op://Dev Vault/db".into()])
+        );
+        assert_eq!(get_source_argv(&[]), None);
+    }
+
+    #[test]
+    fn source_argv_requires_source_cmd() {
+        let result = Cli::try_parse_from(["hemli", "get", "-n", "ns", "sec", "--", "echo", "hi"]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn source_sh_and_source_cmd_conflict() {
         let result = Cli::try_parse_from([
@@ -270,12 +965,509 @@ mod tests {
             Cli::try_parse_from(["hemli", "get", "-n", "ns", "sec", "--ttl", "3600"]).unwrap();
         match cli.command {
             Command::Get { ttl, .. } => {
-                assert_eq!(ttl, Some(3600));
+                assert_eq!(ttl, Some(SignedDuration::from_hours(1)));
+            }
+            _ => panic!("expected Get"),
+        }
+    }
+
+    #[test]
+    fn parse_get_with_friendly_ttl() {
+        for (input, expected) in [
+            ("30m", SignedDuration::from_mins(30)),
+            ("7d", SignedDuration::from_hours(7 * 24)),
+            ("PT12H", SignedDuration::from_hours(12)),
+        ] {
+            let cli = Cli::try_parse_from(["hemli", "get", "sec", "--ttl", input]).unwrap();
+            match cli.command {
+                Command::Get { ttl, .. } => assert_eq!(ttl, Some(expected)),
+                _ => panic!("expected Get"),
+            }
+        }
+    }
+
+    #[test]
+    fn non_positive_ttl_errors() {
+        for bad in ["0", "-60", "-1h", "soon"] {
+            for command in ["get", "set", "edit"] {
+                let result =
+                    Cli::try_parse_from(["hemli", command, "-n", "ns", "sec", "--ttl", bad]);
+                assert!(result.is_err(), "{command} --ttl {bad} should be rejected");
+            }
+        }
+    }
+
+    #[test]
+    fn parse_get_with_source_timeout() {
+        let cli =
+            Cli::try_parse_from(["hemli", "get", "-n", "ns", "sec", "--source-timeout", "2m"])
+                .unwrap();
+        match cli.command {
+            Command::Get { source_timeout, .. } => {
+                assert_eq!(source_timeout, Some(SignedDuration::from_mins(2)));
+            }
+            _ => panic!("expected Get"),
+        }
+    }
+
+    #[test]
+    fn parse_get_with_retries() {
+        let cli = Cli::try_parse_from([
+            "hemli",
+            "get",
+            "-n",
+            "ns",
+            "sec",
+            "--retries",
+            "3",
+            "--retry-backoff",
+            "500ms",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Get {
+                retries,
+                retry_backoff,
+                ..
+            } => {
+                assert_eq!(retries, Some(3));
+                assert_eq!(retry_backoff, Some(SignedDuration::from_millis(500)));
+            }
+            _ => panic!("expected Get"),
+        }
+    }
+
+    #[test]
+    fn retry_backoff_requires_retries() {
+        let result =
+            Cli::try_parse_from(["hemli", "get", "-n", "ns", "sec", "--retry-backoff", "1s"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_get_stale_if_error() {
+        let parse = |args: &[&str]| {
+            let cli = Cli::try_parse_from(["hemli", "get", "-n", "ns"].iter().chain(args)).unwrap();
+            match cli.command {
+                Command::Get { stale_if_error, .. } => stale_if_error,
+                _ => panic!("expected Get"),
+            }
+        };
+        assert_eq!(parse(&["sec"]), None);
+        assert_eq!(parse(&["--stale-if-error", "sec"]), Some(None));
+        assert_eq!(
+            parse(&["--stale-if-error=1d", "sec"]),
+            Some(Some(SignedDuration::from_hours(24)))
+        );
+    }
+
+    #[test]
+    fn parse_get_output_mode() {
+        let cli = Cli::try_parse_from(["hemli", "get", "-n", "ns", "sec", "--output-mode", "raw"])
+            .unwrap();
+        match cli.command {
+            Command::Get { output_mode, .. } => assert_eq!(output_mode, Some(OutputMode::Raw)),
+            _ => panic!("expected Get"),
+        }
+        assert!(Cli::try_parse_from(["hemli", "get", "sec", "--output-mode", "hex"]).is_err());
+    }
+
+    #[test]
+    fn parse_get_field() {
+        let cli = Cli::try_parse_from([
+            "hemli",
+            "get",
+            "-n",
+            "ns",
+            "sec",
+            "--field",
+            "data.password",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Get { field, .. } => assert_eq!(field.as_deref(), Some("data.password")),
+            _ => panic!("expected Get"),
+        }
+    }
+
+    #[test]
+    fn parse_get_fields() {
+        let cli = Cli::try_parse_from([
+            "hemli",
+            "get",
+            "-n",
+            "ns",
+            "db",
+            "--fields",
+            "password=data.password",
+            "--fields",
+            "host",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Get { fields, .. } => assert_eq!(
+                fields,
+                vec![
+                    ("password".to_string(), "data.password".to_string()),
+                    ("host".to_string(), "host".to_string()),
+                ]
+            ),
+            _ => panic!("expected Get"),
+        }
+    }
+
+    #[test]
+    fn malformed_fields_error() {
+        for bad in ["=path", "name=", "a#b=path"] {
+            let result = Cli::try_parse_from(["hemli", "get", "db", "--fields", bad]);
+            assert!(result.is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn parse_get_ttl_from() {
+        let cli = Cli::try_parse_from([
+            "hemli",
+            "get",
+            "-n",
+            "ns",
+            "token",
+            "--ttl-from",
+            "json:lease_duration",
+            "--ttl-margin",
+            "30s",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Get {
+                ttl_from,
+                ttl_margin,
+                ..
+            } => {
+                assert_eq!(ttl_from, Some(TtlFrom::Json("lease_duration".into())));
+                assert_eq!(ttl_margin, Some(SignedDuration::from_secs(30)));
+            }
+            _ => panic!("expected Get"),
+        }
+
+        assert!(Cli::try_parse_from(["hemli", "get", "token", "--ttl-from", "exp"]).is_err());
+        // A margin needs something to subtract it from
+        assert!(Cli::try_parse_from(["hemli", "get", "token", "--ttl-margin", "30s"]).is_err());
+    }
+
+    #[test]
+    fn edit_ttl_from_and_clear_conflict() {
+        let result = Cli::try_parse_from([
+            "hemli",
+            "edit",
+            "-n",
+            "ns",
+            "sec",
+            "--ttl-from",
+            "jwt",
+            "--clear-ttl-from",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_get_expiry_times() {
+        let cli = Cli::try_parse_from([
+            "hemli",
+            "get",
+            "sec",
+            "--expires-at",
+            "2099-01-15T17:00:00Z",
+            "--expires-daily-at",
+            "03:00[UTC]",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Get {
+                expires_at,
+                expires_daily_at,
+                ..
+            } => {
+                assert_eq!(expires_at, Some("2099-01-15T17:00:00Z".parse().unwrap()));
+                assert_eq!(expires_daily_at, Some("03:00[UTC]".parse().unwrap()));
             }
             _ => panic!("expected Get"),
         }
     }
 
+    #[test]
+    fn parse_timestamp_forms() {
+        let utc: Timestamp = "2099-01-15T16:00:00Z".parse().unwrap();
+        assert_eq!(parse_timestamp("2099-01-15T17:00:00+01:00"), Ok(utc));
+        assert_eq!(parse_timestamp("2099-01-15T17:00[Europe/Oslo]"), Ok(utc));
+        assert!(parse_timestamp("2099-01-15T17:00").is_ok());
+        assert!(parse_timestamp("tomorrow").is_err());
+        assert!(parse_timestamp("2020-01-01T00:00:00Z").is_ok());
+        assert!(parse_future_timestamp("2020-01-01T00:00:00Z").is_err());
+        assert!(parse_future_timestamp("2099-01-15T17:00").is_ok());
+    }
+
+    #[test]
+    fn edit_expires_at_and_clear_conflict() {
+        let result = Cli::try_parse_from([
+            "hemli",
+            "edit",
+            "-n",
+            "ns",
+            "sec",
+            "--expires-at",
+            "2025-01-15T17:00:00Z",
+            "--clear-expires-at",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn default_source_timeout_is_global() {
+        let cli =
+            Cli::try_parse_from(["hemli", "list", "--default-source-timeout", "30s"]).unwrap();
+        assert_eq!(
+            cli.default_source_timeout,
+            Some(SignedDuration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn edit_source_timeout_and_clear_conflict() {
+        let result = Cli::try_parse_from([
+            "hemli",
+            "edit",
+            "-n",
+            "ns",
+            "sec",
+            "--source-timeout",
+            "30s",
+            "--clear-source-timeout",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_run() {
+        let cli = Cli::try_parse_from([
+            "hemli",
+            "run",
+            "-n",
+            "myapp",
+            "--env",
+            "DB_PASSWORD=db_password",
+            "--env",
+            "API_KEY=api_key",
+            "--",
+            "cargo",
+            "run",
+            "--release",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Run {
+                namespace,
+                env,
+                command,
+            } => {
+                assert_eq!(namespace.as_deref(), Some("myapp"));
+                assert_eq!(
+                    env,
+                    [
+                        ("DB_PASSWORD".to_string(), "db_password".to_string()),
+                        ("API_KEY".to_string(), "api_key".to_string()),
+                    ]
+                );
+                assert_eq!(command, ["cargo", "run", "--release"]);
+            }
+            _ => panic!("expected Run"),
+        }
+    }
+
+    #[test]
+    fn run_requires_command() {
+        let result = Cli::try_parse_from(["hemli", "run", "-n", "ns", "--env", "A=a"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn run_rejects_malformed_env() {
+        for bad in ["NOEQUALS", "=secret", "VAR="] {
+            let result =
+                Cli::try_parse_from(["hemli", "run", "-n", "ns", "--env", bad, "--", "true"]);
+            assert!(result.is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn parse_set() {
+        let cli = Cli::try_parse_from(["hemli", "set", "-n", "ns", "sec", "--ttl", "60"]).unwrap();
+        match cli.command {
+            Command::Set {
+                namespace,
+                secret,
+                ttl,
+            } => {
+                assert_eq!(namespace, "ns");
+                assert_eq!(secret, "sec");
+                assert_eq!(ttl, Some(SignedDuration::from_secs(60)));
+            }
+            _ => panic!("expected Set"),
+        }
+    }
+
+    #[test]
+    fn parse_refresh_defaults() {
+        let cli = Cli::try_parse_from(["hemli", "refresh"]).unwrap();
+        match cli.command {
+            Command::Refresh {
+                namespace,
+                secrets,
+                all,
+                expired,
+                jobs,
+            } => {
+                assert!(namespace.is_none());
+                assert!(secrets.is_empty());
+                assert!(!all);
+                assert!(!expired);
+                assert_eq!(jobs, 4);
+            }
+            _ => panic!("expected Refresh"),
+        }
+    }
+
+    #[test]
+    fn parse_refresh_all_with_jobs() {
+        let cli =
+            Cli::try_parse_from(["hemli", "refresh", "-n", "ns", "--all", "-j", "8"]).unwrap();
+        match cli.command {
+            Command::Refresh {
+                namespace,
+                all,
+                jobs,
+                ..
+            } => {
+                assert_eq!(namespace.as_deref(), Some("ns"));
+                assert!(all);
+                assert_eq!(jobs, 8);
+            }
+            _ => panic!("expected Refresh"),
+        }
+    }
+
+    #[test]
+    fn parse_refresh_secrets() {
+        let cli = Cli::try_parse_from(["hemli", "refresh", "-n", "ns", "a", "b"]).unwrap();
+        match cli.command {
+            Command::Refresh { secrets, .. } => assert_eq!(secrets, ["a", "b"]),
+            _ => panic!("expected Refresh"),
+        }
+    }
+
+    #[test]
+    fn parse_get_refresh_ahead() {
+        let cli = Cli::try_parse_from(["hemli", "get", "-n", "ns", "sec", "--refresh-ahead", "5m"])
+            .unwrap();
+        match cli.command {
+            Command::Get { refresh_ahead, .. } => {
+                assert_eq!(refresh_ahead, Some(SignedDuration::from_mins(5)));
+            }
+            _ => panic!("expected Get"),
+        }
+    }
+
+    #[test]
+    fn refresh_all_and_expired_conflict() {
+        let result = Cli::try_parse_from(["hemli", "refresh", "--all", "--expired"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn refresh_zero_jobs_errors() {
+        let result = Cli::try_parse_from(["hemli", "refresh", "--jobs", "0"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_purge() {
+        let cli = Cli::try_parse_from([
+            "hemli",
+            "purge",
+            "-n",
+            "ns",
+            "--expired",
+            "--older-than",
+            "30d",
+            "--dry-run",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Purge {
+                namespace,
+                expired,
+                older_than,
+                dry_run,
+            } => {
+                assert_eq!(namespace.as_deref(), Some("ns"));
+                assert!(expired);
+                assert_eq!(older_than, Some(SignedDuration::from_hours(30 * 24)));
+                assert!(dry_run);
+            }
+            _ => panic!("expected Purge"),
+        }
+    }
+
+    #[test]
+    fn purge_requires_criteria() {
+        assert!(Cli::try_parse_from(["hemli", "purge"]).is_err());
+        assert!(Cli::try_parse_from(["hemli", "purge", "--dry-run"]).is_err());
+        assert!(Cli::try_parse_from(["hemli", "purge", "--expired"]).is_ok());
+    }
+
+    #[test]
+    fn parse_doctor() {
+        let cli = Cli::try_parse_from(["hemli", "doctor", "--fix"]).unwrap();
+        match cli.command {
+            Command::Doctor { fix } => assert!(fix),
+            _ => panic!("expected Doctor"),
+        }
+    }
+
+    #[test]
+    fn parse_agent() {
+        let cli = Cli::try_parse_from(["hemli", "agent"]).unwrap();
+        match cli.command {
+            Command::Agent {
+                status,
+                stop,
+                idle_timeout,
+            } => {
+                assert!(!status && !stop);
+                assert!(idle_timeout.is_none());
+            }
+            _ => panic!("expected Agent"),
+        }
+        let cli = Cli::try_parse_from(["hemli", "agent", "--stop"]).unwrap();
+        assert!(matches!(cli.command, Command::Agent { stop: true, .. }));
+        assert!(Cli::try_parse_from(["hemli", "agent", "--status", "--stop"]).is_err());
+        let cli = Cli::try_parse_from(["hemli", "agent", "--idle-timeout", "15m"]).unwrap();
+        match cli.command {
+            Command::Agent { idle_timeout, .. } => {
+                assert_eq!(idle_timeout, Some(SignedDuration::from_mins(15)));
+            }
+            _ => panic!("expected Agent"),
+        }
+        assert!(matches!(
+            Cli::try_parse_from(["hemli", "lock"]).unwrap().command,
+            Command::Lock
+        ));
+        assert!(matches!(
+            Cli::try_parse_from(["hemli", "unlock"]).unwrap().command,
+            Command::Unlock
+        ));
+    }
+
     #[test]
     fn parse_delete() {
         let cli = Cli::try_parse_from(["hemli", "delete", "-n", "myns", "mysecret"]).unwrap();
@@ -294,8 +1486,18 @@ mod tests {
     fn parse_list_no_namespace() {
         let cli = Cli::try_parse_from(["hemli", "list"]).unwrap();
         match cli.command {
-            Command::List { namespace } => {
+            Command::List {
+                namespace,
+                format,
+                expired,
+                valid,
+                expiring_within,
+            } => {
                 assert!(namespace.is_none());
+                assert_eq!(format, ListFormat::Tsv);
+                assert!(!expired);
+                assert!(!valid);
+                assert!(expiring_within.is_none());
             }
             _ => panic!("expected List"),
         }
@@ -305,13 +1507,60 @@ mod tests {
     fn parse_list_with_namespace() {
         let cli = Cli::try_parse_from(["hemli", "list", "-n", "myns"]).unwrap();
         match cli.command {
-            Command::List { namespace } => {
+            Command::List { namespace, .. } => {
                 assert_eq!(namespace.as_deref(), Some("myns"));
             }
             _ => panic!("expected List"),
         }
     }
 
+    #[test]
+    fn parse_list_with_format() {
+        let cli = Cli::try_parse_from(["hemli", "list", "--format", "json"]).unwrap();
+        match cli.command {
+            Command::List { format, .. } => assert_eq!(format, ListFormat::Json),
+            _ => panic!("expected List"),
+        }
+
+        let cli =
+            Cli::try_parse_from(["hemli", "list", "--format", "{namespace}/{secret}"]).unwrap();
+        match cli.command {
+            Command::List { format, .. } => {
+                assert!(matches!(format, ListFormat::Template(_)));
+            }
+            _ => panic!("expected List"),
+        }
+    }
+
+    #[test]
+    fn parse_list_expiring_within() {
+        let cli = Cli::try_parse_from(["hemli", "list", "--expiring-within", "1h"]).unwrap();
+        match cli.command {
+            Command::List {
+                expiring_within, ..
+            } => assert_eq!(expiring_within, Some(SignedDuration::from_hours(1))),
+            _ => panic!("expected List"),
+        }
+    }
+
+    #[test]
+    fn list_expiry_filters_conflict() {
+        for flags in [
+            ["--expired", "--valid"],
+            ["--expired", "--expiring-within=1h"],
+            ["--valid", "--expiring-within=1h"],
+        ] {
+            let result = Cli::try_parse_from(["hemli", "list", flags[0], flags[1]]);
+            assert!(result.is_err(), "{flags:?} should conflict");
+        }
+    }
+
+    #[test]
+    fn list_invalid_format_errors() {
+        let result = Cli::try_parse_from(["hemli", "list", "--format", "{bogus}"]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn parse_inspect() {
         let cli = Cli::try_parse_from(["hemli", "inspect", "-n", "myns", "mysecret"]).unwrap();
@@ -344,13 +1593,57 @@ mod tests {
                 clear_ttl,
                 source_sh,
                 source_cmd,
+                source_argv,
+                source_timeout,
+                clear_source_timeout,
+                retries,
+                retry_backoff,
+                clear_retries,
+                stale_if_error,
+                clear_stale_if_error,
+                output_mode,
+                field,
+                clear_field,
+                fields,
+                clear_fields,
+                ttl_from,
+                ttl_margin,
+                clear_ttl_from,
+                refresh_ahead,
+                clear_refresh_ahead,
+                expires_at,
+                clear_expires_at,
+                expires_daily_at,
+                clear_expires_daily_at,
             } => {
                 assert_eq!(namespace, "myns");
                 assert_eq!(secret, "mysecret");
-                assert_eq!(ttl, Some(7200));
+                assert_eq!(ttl, Some(SignedDuration::from_hours(2)));
                 assert!(!clear_ttl);
                 assert!(source_sh.is_none());
                 assert!(source_cmd.is_none());
+                assert!(source_argv.is_empty());
+                assert!(source_timeout.is_none());
+                assert!(!clear_source_timeout);
+                assert!(retries.is_none());
+                assert!(retry_backoff.is_none());
+                assert!(!clear_retries);
+                assert!(stale_if_error.is_none());
+                assert!(!clear_stale_if_error);
+                assert!(output_mode.is_none());
+                assert!(field.is_none());
+                assert!(!clear_field);
+                assert!(fields.is_empty());
+                assert!(!clear_fields);
+                assert!(ttl_from.is_none());
+                assert!(ttl_margin.is_none());
+                assert!(!clear_ttl_from);
+                assert!(refresh_ahead.is_none());
+                assert!(!clear_refresh_ahead);
+                assert!(expires_at.is_none());
+                assert!(!clear_expires_at);
+                assert!(expires_daily_at.is_none());
+                assert!(!clear_expires_daily_at);
             }
             _ => panic!("expected Edit"),
         }
@@ -420,7 +1713,7 @@ mod tests {
                 ..
             } => {
                 assert!(source_sh.is_none());
-                assert_eq!(source_cmd.as_deref(), Some("my-cmd arg1"));
+                assert_eq!(source_cmd, Some(vec!["my-cmd arg1".to_string()]));
             }
             _ => panic!("expected Edit"),
         }
@@ -449,11 +1742,33 @@ mod tests {
     }
 
     #[test]
-    fn missing_namespace_errors() {
-        let result = Cli::try_parse_from(["hemli", "get", "mysecret"]);
+    fn backend_defaults_to_keyring() {
+        let cli = Cli::try_parse_from(["hemli", "list"]).unwrap();
+        assert_eq!(cli.backend, Backend::Keyring);
+    }
+
+    #[test]
+    fn backend_is_global() {
+        let cli = Cli::try_parse_from(["hemli", "list", "--backend", "keyring"]).unwrap();
+        assert_eq!(cli.backend, Backend::Keyring);
+    }
+
+    #[test]
+    fn unknown_backend_errors() {
+        let result = Cli::try_parse_from(["hemli", "--backend", "nope", "list"]);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn get_namespace_is_optional() {
+        // Falls back to the manifest namespace at runtime
+        let cli = Cli::try_parse_from(["hemli", "get", "mysecret"]).unwrap();
+        match cli.command {
+            Command::Get { namespace, .. } => assert!(namespace.is_none()),
+            _ => panic!("expected Get"),
+        }
+    }
+
     #[test]
     fn missing_secret_name_errors() {
         let result = Cli::try_parse_from(["hemli", "get", "-n", "ns"]);
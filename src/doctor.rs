@@ -0,0 +1,354 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::error::HemliError;
+use crate::index;
+use crate::index::IndexEntry;
+use crate::index::SecretIndex;
+use crate::model::StoredSecret;
+use crate::store::SecretStore;
+
+/// A disagreement between the index and the storage backend.
+#[derive(Debug)]
+pub enum Problem {
+    /// Indexed, but the backend has no such secret
+    Orphaned { namespace: String, secret: String },
+    /// The backend payload is not a valid stored secret
+    Corrupt {
+        namespace: String,
+        secret: String,
+        error: String,
+    },
+    /// The backend refused to return the secret
+    Unreadable {
+        namespace: String,
+        secret: String,
+        error: String,
+    },
+    /// The backend cannot enumerate its contents, so secrets missing from the
+    /// index cannot be found
+    Unlistable { error: String },
+    /// Stored in the backend but missing from the index
+    Unindexed {
+        namespace: String,
+        secret: String,
+        stored: StoredSecret,
+    },
+    /// Index metadata (creation time, TTL, expiry, source type) is out of date
+    Stale {
+        namespace: String,
+        secret: String,
+        stored: StoredSecret,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Orphaned { namespace, secret } => {
+                write!(
+                    f,
+                    "orphaned {namespace}/{secret}: indexed but not in backend"
+                )
+            }
+            Problem::Corrupt {
+                namespace,
+                secret,
+                error,
+            } => write!(f, "corrupt {namespace}/{secret}: {error}"),
+            Problem::Unreadable {
+                namespace,
+                secret,
+                error,
+            } => write!(f, "unreadable {namespace}/{secret}: {error}"),
+            Problem::Unlistable { error } => {
+                write!(f, "unlistable backend: cannot enumerate secrets: {error}")
+            }
+            Problem::Unindexed {
+                namespace, secret, ..
+            } => write!(
+                f,
+                "unindexed {namespace}/{secret}: in backend but not indexed"
+            ),
+            Problem::Stale {
+                namespace, secret, ..
+            } => write!(f, "stale {namespace}/{secret}: index metadata out of date"),
+        }
+    }
+}
+
+impl Problem {
+    /// Whether `--fix` can repair this problem. Payloads that cannot be read
+    /// or parsed are left alone: they may have been written by a newer hemli.
+    pub fn is_fixable(&self) -> bool {
+        !matches!(
+            self,
+            Problem::Corrupt { .. } | Problem::Unreadable { .. } | Problem::Unlistable { .. }
+        )
+    }
+}
+
+/// What [`diagnose`] found.
+#[derive(Debug)]
+pub struct Diagnosis {
+    pub problems: Vec<Problem>,
+    /// The backend cannot enumerate its contents (like the OS keyring), so
+    /// secrets missing from the index were not looked for
+    pub skipped_unindexed: bool,
+}
+
+/// Check every index entry against the backend and, if the backend can
+/// enumerate its contents, every stored secret against the index. A backend
+/// that fails to parse its contents as a whole, like a secrets file with one
+/// corrupt record, is reported as a problem alongside the per-entry ones.
+pub fn diagnose(store: &dyn SecretStore, idx: &SecretIndex) -> Result<Diagnosis, HemliError> {
+    let mut problems = Vec::new();
+
+    for entry in &idx.entries {
+        let namespace = entry.namespace.clone();
+        let secret = entry.secret.clone();
+        match store.get(&entry.namespace, &entry.secret) {
+            Ok(None) => problems.push(Problem::Orphaned { namespace, secret }),
+            Ok(Some(stored)) => {
+                if !matches_index(entry, &stored) {
+                    problems.push(Problem::Stale {
+                        namespace,
+                        secret,
+                        stored,
+                    });
+                }
+            }
+            Err(HemliError::Serialization(e)) => problems.push(Problem::Corrupt {
+                namespace,
+                secret,
+                error: e.to_string(),
+            }),
+            Err(HemliError::Keyring(e)) => problems.push(Problem::Unreadable {
+                namespace,
+                secret,
+                error: e.to_string(),
+            }),
+            // Anything else (wrong passphrase, I/O) affects every secret alike
+            Err(e) => return Err(e),
+        }
+    }
+
+    let (stored_names, skipped_unindexed) = match store.list() {
+        Ok(names) => {
+            let skipped = names.is_none();
+            (names, skipped)
+        }
+        Err(HemliError::Serialization(e)) => {
+            problems.push(Problem::Unlistable {
+                error: e.to_string(),
+            });
+            (None, false)
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some(stored_names) = stored_names {
+        let indexed: HashSet<(&str, &str)> = idx
+            .entries
+            .iter()
+            .map(|e| (e.namespace.as_str(), e.secret.as_str()))
+            .collect();
+        for (namespace, secret) in stored_names {
+            if indexed.contains(&(namespace.as_str(), secret.as_str())) {
+                continue;
+            }
+            if let Some(stored) = store.get(&namespace, &secret)? {
+                problems.push(Problem::Unindexed {
+                    namespace,
+                    secret,
+                    stored,
+                });
+            }
+        }
+    }
+
+    Ok(Diagnosis {
+        problems,
+        skipped_unindexed,
+    })
+}
+
+fn matches_index(entry: &IndexEntry, stored: &StoredSecret) -> bool {
+    entry.created_at == stored.created_at
+        && entry.ttl_seconds == stored.ttl_seconds
+        && entry.expires_at == stored.expires_at
+        && entry.source_type == stored.source_type
+}
+
+/// Repair `problem`, updating `idx` in place. Returns `false` if the problem
+/// cannot be fixed automatically.
+pub fn fix(idx: &mut SecretIndex, problem: &Problem) -> bool {
+    match problem {
+        Problem::Orphaned { namespace, secret } => {
+            index::remove_entry(idx, namespace, secret);
+        }
+        Problem::Corrupt { .. } | Problem::Unreadable { .. } | Problem::Unlistable { .. } => {
+            return false;
+        }
+        Problem::Unindexed {
+            namespace,
+            secret,
+            stored,
+        }
+        | Problem::Stale {
+            namespace,
+            secret,
+            stored,
+        } => index::upsert_entry(idx, namespace, secret, stored),
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::store::FileStore;
+
+    fn file_store(dir: &Path) -> FileStore {
+        let store = FileStore::new(dir.join("secrets.enc"));
+        store.unlock("hunter2").unwrap();
+        store
+    }
+
+    #[test]
+    fn healthy_index_has_no_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = file_store(dir.path());
+        let mut idx = SecretIndex::default();
+        let stored = StoredSecret::new("val".into(), None, None, Some(60));
+        store.set("ns", "sec", &stored).unwrap();
+        index::upsert_entry(&mut idx, "ns", "sec", &stored);
+
+        let diagnosis = diagnose(&store, &idx).unwrap();
+        assert!(diagnosis.problems.is_empty());
+        assert!(!diagnosis.skipped_unindexed);
+    }
+
+    #[test]
+    fn finds_and_fixes_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = file_store(dir.path());
+        let mut idx = SecretIndex::default();
+
+        // Orphaned: indexed only
+        let stored = StoredSecret::new("val".into(), None, None, None);
+        index::upsert_entry(&mut idx, "ns", "orphan", &stored);
+        // Unindexed: stored only
+        store.set("ns", "unindexed", &stored).unwrap();
+        // Stale: TTL changed behind the index's back
+        store.set("ns", "stale", &stored).unwrap();
+        index::upsert_entry(&mut idx, "ns", "stale", &stored);
+        let mut edited = stored.clone();
+        edited.ttl_seconds = Some(60);
+        edited.recalculate_expires_at();
+        store.set("ns", "stale", &edited).unwrap();
+
+        let problems = diagnose(&store, &idx).unwrap().problems;
+        let mut kinds: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        kinds.sort();
+        assert_eq!(kinds.len(), 3);
+        assert!(kinds[0].starts_with("orphaned ns/orphan"));
+        assert!(kinds[1].starts_with("stale ns/stale"));
+        assert!(kinds[2].starts_with("unindexed ns/unindexed"));
+
+        for problem in &problems {
+            assert!(fix(&mut idx, problem));
+        }
+        assert!(diagnose(&store, &idx).unwrap().problems.is_empty());
+        let stale = idx.entries.iter().find(|e| e.secret == "stale").unwrap();
+        assert_eq!(stale.ttl_seconds, Some(60));
+    }
+
+    fn parse_error() -> HemliError {
+        serde_json::from_str::<StoredSecret>("{")
+            .unwrap_err()
+            .into()
+    }
+
+    /// A backend whose contents cannot be parsed, like a secrets file with a
+    /// corrupt record. Counts writes, which doctor must never make.
+    #[derive(Default)]
+    struct CorruptStore {
+        writes: AtomicUsize,
+    }
+
+    impl SecretStore for CorruptStore {
+        fn get(&self, _: &str, _: &str) -> Result<Option<StoredSecret>, HemliError> {
+            Err(parse_error())
+        }
+
+        fn set(&self, _: &str, _: &str, _: &StoredSecret) -> Result<(), HemliError> {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn delete(&self, _: &str, _: &str) -> Result<(), HemliError> {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn list(&self) -> Result<Option<Vec<(String, String)>>, HemliError> {
+            Err(parse_error())
+        }
+    }
+
+    #[test]
+    fn corrupt_payloads_are_only_reported() {
+        let store = CorruptStore::default();
+        let mut idx = SecretIndex::default();
+        let stored = StoredSecret::new("val".into(), None, None, None);
+        index::upsert_entry(&mut idx, "ns", "a", &stored);
+        index::upsert_entry(&mut idx, "ns", "b", &stored);
+
+        let problems = diagnose(&store, &idx).unwrap().problems;
+        let kinds: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(kinds.len(), 3);
+        assert!(kinds[0].starts_with("corrupt ns/a"));
+        assert!(kinds[1].starts_with("corrupt ns/b"));
+        assert!(kinds[2].starts_with("unlistable backend"));
+
+        for problem in &problems {
+            assert!(!problem.is_fixable());
+            assert!(!fix(&mut idx, problem));
+        }
+        assert_eq!(idx.entries.len(), 2);
+        assert_eq!(store.writes.load(Ordering::Relaxed), 0);
+    }
+
+    /// A backend that cannot enumerate its contents, like the OS keyring.
+    struct UnlistableStore;
+
+    impl SecretStore for UnlistableStore {
+        fn get(&self, _: &str, _: &str) -> Result<Option<StoredSecret>, HemliError> {
+            Ok(Some(StoredSecret::new("val".into(), None, None, None)))
+        }
+
+        fn set(&self, _: &str, _: &str, _: &StoredSecret) -> Result<(), HemliError> {
+            Ok(())
+        }
+
+        fn delete(&self, _: &str, _: &str) -> Result<(), HemliError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn unlistable_backend_skips_the_unindexed_check() {
+        let diagnosis = diagnose(&UnlistableStore, &SecretIndex::default()).unwrap();
+        assert!(diagnosis.problems.is_empty());
+        assert!(diagnosis.skipped_unindexed);
+
+        // Unparseable contents are a problem of their own, not a skip
+        let diagnosis = diagnose(&CorruptStore::default(), &SecretIndex::default()).unwrap();
+        assert_eq!(diagnosis.problems.len(), 1);
+        assert!(!diagnosis.skipped_unindexed);
+    }
+}
@@ -0,0 +1,133 @@
+use jiff::SignedDuration;
+use jiff::Span;
+use jiff::SpanRelativeTo;
+use jiff::SpanRound;
+use jiff::Unit;
+
+use crate::error::HemliError;
+
+/// Parse a positive duration such as `90s`, `30m`, `12h`, `7d`, `1h 30m`, or
+/// an ISO 8601 duration like `PT1H` or `P7D`. A bare integer is a number of
+/// seconds. Days and weeks are 24 and 168 hours; months and years are
+/// rejected since their length varies.
+pub fn parse(input: &str) -> Result<SignedDuration, HemliError> {
+    let invalid = |reason: String| HemliError::InvalidDuration {
+        input: input.to_string(),
+        reason,
+    };
+    let trimmed = input.trim();
+    let duration = if let Ok(secs) = trimmed.parse::<i64>() {
+        SignedDuration::from_secs(secs)
+    } else {
+        let span: Span = trimmed
+            .parse()
+            .map_err(|e: jiff::Error| invalid(e.to_string()))?;
+        span.to_duration(SpanRelativeTo::days_are_24_hours())
+            .map_err(|e| invalid(e.to_string()))?
+    };
+    if !duration.is_positive() {
+        return Err(invalid("must be greater than zero".into()));
+    }
+    Ok(duration)
+}
+
+/// Whole seconds in a positive duration, rounding up so that sub-second
+/// durations never become zero.
+pub fn ceil_seconds(duration: SignedDuration) -> u64 {
+    let secs = duration.as_secs().unsigned_abs();
+    if duration.subsec_nanos() != 0 {
+        secs + 1
+    } else {
+        secs
+    }
+}
+
+/// Whole seconds in a TTL, as stored with a secret.
+pub fn ttl_seconds(duration: SignedDuration) -> i64 {
+    i64::try_from(ceil_seconds(duration)).unwrap_or(i64::MAX)
+}
+
+/// Render a number of seconds the way [`parse`] reads it, such as `90s`,
+/// `1h 30m`, or `7d`.
+pub fn format_seconds(secs: i64) -> String {
+    Span::new()
+        .try_seconds(secs)
+        .and_then(|span| {
+            span.round(
+                SpanRound::new()
+                    .largest(Unit::Day)
+                    .relative(SpanRelativeTo::days_are_24_hours()),
+            )
+        })
+        .map(|span| format!("{span:#}"))
+        .unwrap_or_else(|_| format!("{secs}s"))
+}
+
+/// [`parse`] adapted for use as a clap `value_parser`.
+pub fn parse_arg(input: &str) -> Result<SignedDuration, String> {
+    parse(input).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn friendly_units() {
+        assert_eq!(parse("90s").unwrap(), SignedDuration::from_secs(90));
+        assert_eq!(parse("30m").unwrap(), SignedDuration::from_mins(30));
+        assert_eq!(parse("12h").unwrap(), SignedDuration::from_hours(12));
+        assert_eq!(parse("7d").unwrap(), SignedDuration::from_hours(7 * 24));
+        assert_eq!(parse("1w").unwrap(), SignedDuration::from_hours(7 * 24));
+        assert_eq!(parse("1h 30m").unwrap(), SignedDuration::from_mins(90));
+    }
+
+    #[test]
+    fn iso8601() {
+        assert_eq!(parse("PT1H").unwrap(), SignedDuration::from_hours(1));
+        assert_eq!(parse("P1DT12H").unwrap(), SignedDuration::from_hours(36));
+    }
+
+    #[test]
+    fn ceil_seconds_rounds_up() {
+        assert_eq!(ceil_seconds(parse("90s").unwrap()), 90);
+        assert_eq!(ceil_seconds(parse("500ms").unwrap()), 1);
+        assert_eq!(ceil_seconds(parse("1.5s").unwrap()), 2);
+    }
+
+    #[test]
+    fn format_roundtrips() {
+        for (secs, text) in [
+            (90, "1m 30s"),
+            (3600, "1h"),
+            (5400, "1h 30m"),
+            (7 * 86400, "7d"),
+            (86400 + 1, "1d 1s"),
+        ] {
+            assert_eq!(format_seconds(secs), text);
+            assert_eq!(ttl_seconds(parse(text).unwrap()), secs);
+        }
+    }
+
+    #[test]
+    fn bare_integer_is_seconds() {
+        assert_eq!(parse("3600").unwrap(), SignedDuration::from_secs(3600));
+    }
+
+    #[test]
+    fn rejects_non_positive() {
+        for input in ["0", "0s", "-5", "-1h"] {
+            match parse(input) {
+                Err(HemliError::InvalidDuration { .. }) => {}
+                other => panic!("{input}: expected InvalidDuration, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_garbage_and_calendar_units() {
+        for input in ["", "soon", "1x", "1mo", "1y"] {
+            assert!(parse(input).is_err(), "{input} should be rejected");
+        }
+    }
+}
@@ -7,13 +7,81 @@ pub enum HemliError {
     NoSource,
 
     #[error(
-        "no modifications specified; provide at least one of --ttl, --clear-ttl, --source-sh, or --source-cmd"
+        "no namespace given; pass --namespace, set HEMLI_NAMESPACE, or declare one in hemli.toml"
     )]
+    NoNamespace,
+
+    #[error("invalid manifest '{path}': {message}")]
+    Manifest { path: String, message: String },
+
+    #[error("no modifications specified; see 'hemli edit --help' for what can be changed")]
     NoModifications,
 
+    #[error("invalid duration '{input}': {reason}")]
+    InvalidDuration { input: String, reason: String },
+
+    #[error("refusing to store an empty secret")]
+    EmptyValue,
+
     #[error("source command failed: {0}")]
     SourceFailed(String),
 
+    #[error("stored value is corrupt: {0}")]
+    InvalidValue(String),
+
+    #[error("source output is not valid JSON: {0}")]
+    InvalidJson(String),
+
+    #[error("field '{0}' not found in source output")]
+    FieldNotFound(String),
+
+    #[error("secret name '{0}' contains '#', which separates a secret from one of its fields")]
+    InvalidName(String),
+
+    #[error("secret has no field '{field}' (available: {available})")]
+    UnknownField { field: String, available: String },
+
+    #[error("source command timed out after {0:?}")]
+    SourceTimeout(std::time::Duration),
+
+    #[error("could not determine expiry from source: {0}")]
+    SourceExpiry(String),
+
+    #[error("no passphrase available; set HEMLI_PASSPHRASE or run hemli from a terminal")]
+    NoPassphrase,
+
+    #[error("passphrases do not match")]
+    PassphraseMismatch,
+
+    #[error("failed to decrypt secrets file '{0}'; wrong passphrase?")]
+    Decrypt(String),
+
+    #[error("secrets file error: {0}")]
+    SecretsFile(String),
+
+    #[error("failed to execute '{program}': {source}")]
+    Exec {
+        program: String,
+        source: std::io::Error,
+    },
+
+    #[error("failed to refresh {failed} of {total} secrets")]
+    RefreshFailed { failed: usize, total: usize },
+
+    #[error("failed to purge {failed} of {total} secrets")]
+    PurgeFailed { failed: usize, total: usize },
+
+    #[error("{0} problems remain; see above")]
+    Unhealthy(usize),
+
+    #[error("agent: {0}")]
+    Agent(String),
+
+    #[error(
+        "the secret store is locked; unlock it again first (for an agent, with 'hemli unlock')"
+    )]
+    Locked,
+
     #[error(transparent)]
     Keyring(#[from] keyring::Error),
 
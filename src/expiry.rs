@@ -0,0 +1,175 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use jiff::SignedDuration;
+use jiff::Timestamp;
+use serde_json::Value;
+
+use crate::error::HemliError;
+use crate::field;
+use crate::model::TtlFrom;
+
+/// Numbers of seconds from this on, in September 2001, are read as Unix
+/// timestamps (like an `expires_at` of 1760000000) rather than as durations
+/// of over 31 years.
+const EPOCH_THRESHOLD: f64 = 1_000_000_000.0;
+
+/// The expiry reported by a fetched secret. `output` is the raw source
+/// output, `value` the resulting secret value, and `fetched_at` the time
+/// relative expiries count from.
+pub fn derive(
+    ttl_from: &TtlFrom,
+    output: &[u8],
+    value: &[u8],
+    fetched_at: Timestamp,
+) -> Result<Timestamp, HemliError> {
+    match ttl_from {
+        TtlFrom::Jwt => jwt_expiry(value),
+        TtlFrom::Json(path) => json_expiry(output, path, fetched_at),
+    }
+}
+
+fn jwt_expiry(token: &[u8]) -> Result<Timestamp, HemliError> {
+    let invalid = |reason: &str| HemliError::SourceExpiry(format!("value is not a JWT: {reason}"));
+    let token = std::str::from_utf8(token).map_err(|_| invalid("not UTF-8"))?;
+    let mut parts = token.trim().split('.');
+    let (Some(_header), Some(payload), Some(_signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid("expected three dot-separated parts"));
+    };
+    let payload = BASE64_URL
+        .decode(payload.trim_end_matches('='))
+        .map_err(|_| invalid("payload is not base64url"))?;
+    let claims: Value =
+        serde_json::from_slice(&payload).map_err(|_| invalid("payload is not JSON"))?;
+    let exp = claims
+        .get("exp")
+        .and_then(Value::as_f64)
+        .ok_or_else(|| HemliError::SourceExpiry("JWT has no numeric 'exp' claim".into()))?;
+    // A NumericDate may have a fractional part; out-of-range values saturate
+    // and are rejected below
+    Timestamp::from_second(exp.floor() as i64)
+        .map_err(|e| HemliError::SourceExpiry(format!("JWT 'exp' is out of range: {e}")))
+}
+
+fn json_expiry(output: &[u8], path: &str, fetched_at: Timestamp) -> Result<Timestamp, HemliError> {
+    let json: Value =
+        serde_json::from_slice(output).map_err(|e| HemliError::InvalidJson(e.to_string()))?;
+    let value =
+        field::select(&json, path).ok_or_else(|| HemliError::FieldNotFound(path.to_string()))?;
+    let out_of_range = |e: jiff::Error| HemliError::SourceExpiry(format!("'{path}': {e}"));
+    match value {
+        Value::Number(n) => {
+            let secs = n.as_f64().ok_or_else(|| {
+                HemliError::SourceExpiry(format!("'{path}' is not a number of seconds"))
+            })?;
+            if secs >= EPOCH_THRESHOLD {
+                // Out-of-range values saturate and are rejected by jiff
+                return Timestamp::from_second(secs.floor() as i64).map_err(out_of_range);
+            }
+            let duration = SignedDuration::try_from_secs_f64(secs).map_err(out_of_range)?;
+            fetched_at.checked_add(duration).map_err(out_of_range)
+        }
+        Value::String(s) => s
+            .parse::<Timestamp>()
+            .map_err(|e| HemliError::SourceExpiry(format!("'{path}' is not a timestamp: {e}"))),
+        _ => Err(HemliError::SourceExpiry(format!(
+            "'{path}' is neither a number of seconds nor a timestamp"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> Timestamp {
+        "2025-01-15T10:00:00Z".parse().unwrap()
+    }
+
+    fn jwt(claims: &str) -> String {
+        format!(
+            "{}.{}.signature",
+            BASE64_URL.encode(r#"{"alg":"HS256"}"#),
+            BASE64_URL.encode(claims)
+        )
+    }
+
+    #[test]
+    fn jwt_exp_claim() {
+        let token = jwt(r#"{"sub":"me","exp":1736938800}"#);
+        let exp = derive(&TtlFrom::Jwt, b"", token.as_bytes(), now()).unwrap();
+        assert_eq!(exp, Timestamp::from_second(1736938800).unwrap());
+    }
+
+    #[test]
+    fn jwt_fractional_exp_claim() {
+        let token = jwt(r#"{"exp":1736938800.75}"#);
+        let exp = derive(&TtlFrom::Jwt, b"", token.as_bytes(), now()).unwrap();
+        assert_eq!(exp, Timestamp::from_second(1736938800).unwrap());
+    }
+
+    #[test]
+    fn jwt_errors() {
+        let no_exp = jwt(r#"{"sub":"me"}"#);
+        let huge_exp = jwt(r#"{"exp":1e300}"#);
+        for value in ["not-a-jwt", "a.b.c", no_exp.as_str(), huge_exp.as_str()] {
+            assert!(
+                matches!(
+                    derive(&TtlFrom::Jwt, b"", value.as_bytes(), now()),
+                    Err(HemliError::SourceExpiry(_))
+                ),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_lease_duration_is_relative() {
+        let output = br#"{"lease_duration": 3600, "data": {}}"#;
+        let exp = derive(&TtlFrom::Json("lease_duration".into()), output, b"", now()).unwrap();
+        assert_eq!(exp, "2025-01-15T11:00:00Z".parse().unwrap());
+    }
+
+    #[test]
+    fn json_unix_timestamp_is_absolute() {
+        let output = br#"{"expires_at": 1736951400}"#;
+        let exp = derive(&TtlFrom::Json("expires_at".into()), output, b"", now()).unwrap();
+        assert_eq!(exp, "2025-01-15T14:30:00Z".parse().unwrap());
+
+        // Just below the threshold, a (very long) duration
+        let output = br#"{"expires_in": 999999999}"#;
+        let exp = derive(&TtlFrom::Json("expires_in".into()), output, b"", now()).unwrap();
+        assert_eq!(
+            exp,
+            now()
+                .checked_add(SignedDuration::from_secs(999_999_999))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn json_timestamp_is_absolute() {
+        let output = br#"{"Credentials": {"Expiration": "2025-01-15T12:30:00+00:00"}}"#;
+        let ttl_from = TtlFrom::Json("/Credentials/Expiration".into());
+        let exp = derive(&ttl_from, output, b"", now()).unwrap();
+        assert_eq!(exp, "2025-01-15T12:30:00Z".parse().unwrap());
+    }
+
+    #[test]
+    fn json_errors() {
+        let ttl_from = TtlFrom::Json("expiry".into());
+        assert!(matches!(
+            derive(&ttl_from, b"{}", b"", now()),
+            Err(HemliError::FieldNotFound(_))
+        ));
+        assert!(matches!(
+            derive(&ttl_from, br#"{"expiry": "soon"}"#, b"", now()),
+            Err(HemliError::SourceExpiry(_))
+        ));
+        assert!(matches!(
+            derive(&ttl_from, br#"{"expiry": true}"#, b"", now()),
+            Err(HemliError::SourceExpiry(_))
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::error::HemliError;
+
+/// Find the value at `path` in `json`.
+///
+/// A path starting with `/` is a JSON pointer (RFC 6901), such as
+/// `/data/data/password`. Anything else is a dotted path like
+/// `data.data.password`, where numeric segments index into arrays.
+pub fn select<'a>(json: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.starts_with('/') {
+        return json.pointer(path);
+    }
+    path.split('.')
+        .try_fold(json, |current, segment| match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+}
+
+/// The text of a selected value: strings as they are, anything else as
+/// compact JSON.
+pub fn to_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Parse `output` as JSON and return the field at `path` as bytes.
+pub fn extract(output: &[u8], path: &str) -> Result<Vec<u8>, HemliError> {
+    let json: Value =
+        serde_json::from_slice(output).map_err(|e| HemliError::InvalidJson(e.to_string()))?;
+    let value = select(&json, path).ok_or_else(|| HemliError::FieldNotFound(path.to_string()))?;
+    Ok(to_text(value).into_bytes())
+}
+
+/// Parse `output` as JSON once and return the text of each named field, given
+/// as name to path. Output is only parsed if there are fields to extract.
+pub fn extract_all(
+    output: &[u8],
+    fields: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, HemliError> {
+    if fields.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let json: Value =
+        serde_json::from_slice(output).map_err(|e| HemliError::InvalidJson(e.to_string()))?;
+    fields
+        .iter()
+        .map(|(name, path)| {
+            let value =
+                select(&json, path).ok_or_else(|| HemliError::FieldNotFound(path.to_string()))?;
+            Ok((name.clone(), to_text(value)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn vault_output() -> Value {
+        json!({
+            "data": {
+                "data": { "password": "hunter2", "port": 5432 },
+                "metadata": { "version": 3 }
+            },
+            "warnings": null,
+            "hosts": ["db-1", "db-2"]
+        })
+    }
+
+    #[test]
+    fn dotted_path() {
+        let json = vault_output();
+        assert_eq!(select(&json, "data.data.password"), Some(&json!("hunter2")));
+        assert_eq!(select(&json, "hosts.1"), Some(&json!("db-2")));
+    }
+
+    #[test]
+    fn json_pointer() {
+        let json = vault_output();
+        assert_eq!(
+            select(&json, "/data/data/password"),
+            Some(&json!("hunter2"))
+        );
+        assert_eq!(select(&json, "/hosts/0"), Some(&json!("db-1")));
+    }
+
+    #[test]
+    fn missing_paths() {
+        let json = vault_output();
+        assert_eq!(select(&json, "data.nope"), None);
+        assert_eq!(select(&json, "hosts.7"), None);
+        assert_eq!(select(&json, "hosts.first"), None);
+        assert_eq!(select(&json, "data.data.password.deeper"), None);
+        assert_eq!(select(&json, "/data/nope"), None);
+    }
+
+    #[test]
+    fn non_string_values_are_json() {
+        let output = vault_output().to_string();
+        assert_eq!(
+            extract(output.as_bytes(), "data.data.port").unwrap(),
+            b"5432"
+        );
+        assert_eq!(
+            extract(output.as_bytes(), "data.metadata").unwrap(),
+            br#"{"version":3}"#
+        );
+    }
+
+    #[test]
+    fn extract_all_fields() {
+        let output = vault_output().to_string();
+        let fields = BTreeMap::from([
+            ("password".to_string(), "data.data.password".to_string()),
+            ("port".to_string(), "/data/data/port".to_string()),
+        ]);
+        let values = extract_all(output.as_bytes(), &fields).unwrap();
+        assert_eq!(values["password"], "hunter2");
+        assert_eq!(values["port"], "5432");
+
+        let missing = BTreeMap::from([("x".to_string(), "nope".to_string())]);
+        assert!(matches!(
+            extract_all(output.as_bytes(), &missing),
+            Err(HemliError::FieldNotFound(_))
+        ));
+        // Nothing to extract, so plain text is fine
+        assert!(extract_all(b"plain", &BTreeMap::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn extract_errors() {
+        assert!(matches!(
+            extract(b"not json", "a"),
+            Err(HemliError::InvalidJson(_))
+        ));
+        match extract(br#"{"a": 1}"#, "b").unwrap_err() {
+            HemliError::FieldNotFound(path) => assert_eq!(path, "b"),
+            other => panic!("expected FieldNotFound, got {other:?}"),
+        }
+    }
+}
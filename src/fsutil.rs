@@ -0,0 +1,220 @@
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::HemliError;
+
+/// An exclusive advisory lock on a file, held until dropped.
+///
+/// Locks are per open file description, so they also exclude other handles
+/// within the same process.
+#[derive(Debug)]
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Block until the lock at `path` is acquired, creating the file if needed.
+    pub fn acquire(path: &Path) -> Result<Self, HemliError> {
+        let file = open_lock_file(path)?;
+        file.lock()?;
+        Ok(Self { _file: file })
+    }
+
+    /// Acquire the lock at `path` if it is free, without blocking.
+    pub fn try_acquire(path: &Path) -> Result<Option<Self>, HemliError> {
+        let file = open_lock_file(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(fs::TryLockError::WouldBlock) => Ok(None),
+            Err(fs::TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+}
+
+/// The lock serializing source fetches of one secret across processes.
+///
+/// Names are hex-encoded so any namespace or secret name maps to a valid,
+/// unambiguous file name.
+pub fn fetch_lock_path(namespace: &str, secret: &str) -> PathBuf {
+    let hex = |s: &str| s.bytes().map(|b| format!("{b:02x}")).collect::<String>();
+    let data_dir = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    data_dir
+        .join("hemli")
+        .join("locks")
+        .join(format!("{}-{}.lock", hex(namespace), hex(secret)))
+}
+
+/// How long a pending background refresh is waited for. The refresh clears
+/// its mark as soon as it tries the fetch lock, so an older mark was left by
+/// one that never got that far.
+const PENDING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Mark a background refresh of the secret whose fetch lock is at
+/// `lock_path` as pending, so that no other process starts one too. Returns
+/// `false` if one is pending already.
+pub fn mark_refresh_pending(lock_path: &Path) -> Result<bool, HemliError> {
+    let path = sibling(lock_path, "pending");
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A second attempt after replacing a mark that is too old
+    for _ in 0..2 {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => return Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+        let age = match fs::metadata(&path).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified.elapsed().unwrap_or_default(),
+            // Cleared in the meantime
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if age < PENDING_TIMEOUT {
+            return Ok(false);
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(false)
+}
+
+/// Clear the mark set by [`mark_refresh_pending`], if any.
+pub fn clear_refresh_pending(lock_path: &Path) {
+    let _ = fs::remove_file(sibling(lock_path, "pending"));
+}
+
+fn open_lock_file(path: &Path) -> Result<File, HemliError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?)
+}
+
+/// The lock file guarding writes to `path`.
+pub fn lock_path(path: &Path) -> PathBuf {
+    sibling(path, "lock")
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Replace `path` with `contents` so that readers see either the old or the
+/// new file, never a partial one. The file is readable only by the current
+/// user.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), HemliError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = sibling(path, &format!("{}.tmp", std::process::id()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let result = options.open(&tmp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomic_replaces_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub").join("file.json");
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        // No temp files left behind
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn lock_path_is_sibling() {
+        assert_eq!(
+            lock_path(Path::new("/data/hemli/index.json")),
+            Path::new("/data/hemli/index.json.lock")
+        );
+    }
+
+    #[test]
+    fn fetch_lock_paths_are_distinct() {
+        let a = fetch_lock_path("ns", "a-b");
+        let b = fetch_lock_path("ns-a", "b");
+        assert_ne!(a, b);
+        assert_eq!(a.file_name().unwrap(), "6e73-612d62.lock");
+    }
+
+    #[test]
+    fn try_acquire_fails_while_held() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x.lock");
+        let guard = FileLock::try_acquire(&path).unwrap();
+        assert!(guard.is_some());
+        assert!(FileLock::try_acquire(&path).unwrap().is_none());
+        drop(guard);
+        assert!(FileLock::try_acquire(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn refresh_pending_until_cleared_or_too_old() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = dir.path().join("locks").join("x.lock");
+        assert!(mark_refresh_pending(&lock).unwrap());
+        assert!(!mark_refresh_pending(&lock).unwrap());
+        clear_refresh_pending(&lock);
+        assert!(mark_refresh_pending(&lock).unwrap());
+
+        // A mark left by a refresh that never started
+        let mark = fs::OpenOptions::new()
+            .write(true)
+            .open(sibling(&lock, "pending"))
+            .unwrap();
+        let old = std::time::SystemTime::now() - PENDING_TIMEOUT - Duration::from_secs(1);
+        mark.set_modified(old).unwrap();
+        assert!(mark_refresh_pending(&lock).unwrap());
+        assert!(!mark_refresh_pending(&lock).unwrap());
+    }
+
+    #[test]
+    fn lock_excludes_other_handles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x.lock");
+        let guard = FileLock::acquire(&path).unwrap();
+        let other = open_lock_file(&path).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(guard);
+        assert!(other.try_lock().is_ok());
+    }
+}
@@ -2,17 +2,67 @@ use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
+use jiff::SignedDuration;
 use jiff::Timestamp;
 use serde::Deserialize;
 use serde::Serialize;
 
 use crate::error::HemliError;
+use crate::fsutil;
+use crate::fsutil::FileLock;
+use crate::model::SourceType;
+use crate::model::StoredSecret;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexEntry {
     pub namespace: String,
     pub secret: String,
     pub created_at: Timestamp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_type: Option<SourceType>,
+}
+
+/// Which entries to keep, based on their recorded expiry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExpiryFilter {
+    #[default]
+    All,
+    Expired,
+    Valid,
+    /// Not yet expired, but expiring within the given duration
+    ExpiringWithin(SignedDuration),
+}
+
+impl ExpiryFilter {
+    pub fn matches(&self, entry: &IndexEntry, now: Timestamp) -> bool {
+        match self {
+            ExpiryFilter::All => true,
+            ExpiryFilter::Expired => entry.is_expired_at(now),
+            ExpiryFilter::Valid => !entry.is_expired_at(now),
+            ExpiryFilter::ExpiringWithin(window) => match entry.expires_at {
+                Some(exp) => now <= exp && exp.duration_since(now) <= *window,
+                None => false,
+            },
+        }
+    }
+}
+
+impl IndexEntry {
+    /// Entries without a recorded expiry never expire.
+    pub fn is_expired_at(&self, now: Timestamp) -> bool {
+        self.expires_at.is_some_and(|exp| now > exp)
+    }
+
+    fn update_from(&mut self, stored: &StoredSecret) {
+        self.created_at = stored.created_at;
+        self.ttl_seconds = stored.ttl_seconds;
+        self.expires_at = stored.expires_at;
+        self.source_type = stored.source_type;
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -25,40 +75,90 @@ pub fn index_path() -> PathBuf {
     data_dir.join("hemli").join("index.json")
 }
 
+/// Load the index. A missing file is an empty index; so is a corrupt one,
+/// after printing a warning, so that one bad write doesn't break every command.
 pub fn load_index(path: &Path) -> Result<SecretIndex, HemliError> {
+    Ok(read_index(path)?.unwrap_or_default())
+}
+
+/// `None` if the file exists but cannot be parsed.
+fn read_index(path: &Path) -> Result<Option<SecretIndex>, HemliError> {
     if !path.exists() {
-        return Ok(SecretIndex::default());
+        return Ok(Some(SecretIndex::default()));
     }
     let contents = fs::read_to_string(path)?;
-    let index: SecretIndex = serde_json::from_str(&contents)?;
-    Ok(index)
+    match serde_json::from_str(&contents) {
+        Ok(index) => Ok(Some(index)),
+        Err(e) => {
+            eprintln!(
+                "warning: ignoring corrupt index '{}': {e}; run 'hemli doctor --fix' to rebuild it",
+                path.display()
+            );
+            Ok(None)
+        }
+    }
 }
 
 pub fn save_index(path: &Path, index: &SecretIndex) -> Result<(), HemliError> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
-    }
     let json = serde_json::to_string_pretty(index)?;
-    fs::write(path, json)?;
-    Ok(())
+    fsutil::write_atomic(path, json.as_bytes())
+}
+
+/// Read-modify-write the index under an exclusive lock, so that concurrent
+/// hemli processes don't lose each other's updates.
+///
+/// A corrupt index is set aside as `<path>.corrupt` and replaced.
+pub fn update_index<T>(
+    path: &Path,
+    f: impl FnOnce(&mut SecretIndex) -> T,
+) -> Result<T, HemliError> {
+    let _lock = FileLock::acquire(&fsutil::lock_path(path))?;
+    let mut index = match read_index(path)? {
+        Some(index) => index,
+        None => {
+            let mut backup = path.as_os_str().to_os_string();
+            backup.push(".corrupt");
+            fs::rename(path, &backup)?;
+            SecretIndex::default()
+        }
+    };
+    let result = f(&mut index);
+    save_index(path, &index)?;
+    Ok(result)
 }
 
-pub fn upsert_entry(index: &mut SecretIndex, namespace: &str, secret: &str, created_at: Timestamp) {
+pub fn upsert_entry(index: &mut SecretIndex, namespace: &str, secret: &str, stored: &StoredSecret) {
     if let Some(entry) = index
         .entries
         .iter_mut()
         .find(|e| e.namespace == namespace && e.secret == secret)
     {
-        entry.created_at = created_at;
+        entry.update_from(stored);
     } else {
-        index.entries.push(IndexEntry {
+        let mut entry = IndexEntry {
             namespace: namespace.to_string(),
             secret: secret.to_string(),
-            created_at,
-        });
+            created_at: stored.created_at,
+            ttl_seconds: None,
+            expires_at: None,
+            source_type: None,
+        };
+        entry.update_from(stored);
+        index.entries.push(entry);
     }
 }
 
+/// Add or update the index entry of a secret that was just stored.
+pub fn record_secret(
+    namespace: &str,
+    secret: &str,
+    stored: &StoredSecret,
+) -> Result<(), HemliError> {
+    update_index(&index_path(), |idx| {
+        upsert_entry(idx, namespace, secret, stored)
+    })
+}
+
 pub fn remove_entry(index: &mut SecretIndex, namespace: &str, secret: &str) {
     index
         .entries
@@ -76,6 +176,12 @@ pub fn filter_entries<'a>(index: &'a SecretIndex, namespace: Option<&str>) -> Ve
 mod tests {
     use super::*;
 
+    fn stored_at(created_at: Timestamp) -> StoredSecret {
+        let mut stored = StoredSecret::new("val".into(), None, None, None);
+        stored.created_at = created_at;
+        stored
+    }
+
     #[test]
     fn load_nonexistent_returns_empty() {
         let dir = tempfile::tempdir().unwrap();
@@ -89,7 +195,7 @@ mod tests {
         let dir = tempfile::tempdir().unwrap();
         let path = dir.path().join("index.json");
         let mut index = SecretIndex::default();
-        upsert_entry(&mut index, "ns1", "sec1", Timestamp::now());
+        upsert_entry(&mut index, "ns1", "sec1", &stored_at(Timestamp::now()));
         save_index(&path, &index).unwrap();
 
         let loaded = load_index(&path).unwrap();
@@ -103,8 +209,8 @@ mod tests {
         let mut index = SecretIndex::default();
         let t1 = Timestamp::from_second(1000).unwrap();
         let t2 = Timestamp::from_second(2000).unwrap();
-        upsert_entry(&mut index, "ns", "sec", t1);
-        upsert_entry(&mut index, "ns", "sec", t2);
+        upsert_entry(&mut index, "ns", "sec", &stored_at(t1));
+        upsert_entry(&mut index, "ns", "sec", &stored_at(t2));
         assert_eq!(index.entries.len(), 1);
         assert_eq!(index.entries[0].created_at, t2);
     }
@@ -113,17 +219,54 @@ mod tests {
     fn upsert_adds_different_entries() {
         let mut index = SecretIndex::default();
         let t = Timestamp::now();
-        upsert_entry(&mut index, "ns1", "sec1", t);
-        upsert_entry(&mut index, "ns2", "sec2", t);
+        upsert_entry(&mut index, "ns1", "sec1", &stored_at(t));
+        upsert_entry(&mut index, "ns2", "sec2", &stored_at(t));
         assert_eq!(index.entries.len(), 2);
     }
 
+    #[test]
+    fn upsert_records_expiry_metadata() {
+        let mut index = SecretIndex::default();
+        let stored = StoredSecret::new(
+            "val".into(),
+            Some("echo hi".into()),
+            Some(SourceType::Sh),
+            Some(3600),
+        );
+        upsert_entry(&mut index, "ns", "sec", &stored);
+        let entry = &index.entries[0];
+        assert_eq!(entry.ttl_seconds, Some(3600));
+        assert_eq!(entry.expires_at, stored.expires_at);
+        assert_eq!(entry.source_type, Some(SourceType::Sh));
+
+        // Metadata is cleared when the secret no longer has it
+        upsert_entry(&mut index, "ns", "sec", &stored_at(Timestamp::now()));
+        let entry = &index.entries[0];
+        assert!(entry.ttl_seconds.is_none());
+        assert!(entry.expires_at.is_none());
+        assert!(entry.source_type.is_none());
+    }
+
+    #[test]
+    fn load_entries_without_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        fs::write(
+            &path,
+            r#"{"entries":[{"namespace":"ns","secret":"sec","created_at":"2025-01-15T10:30:00Z"}]}"#,
+        )
+        .unwrap();
+        let index = load_index(&path).unwrap();
+        assert_eq!(index.entries.len(), 1);
+        assert!(index.entries[0].expires_at.is_none());
+    }
+
     #[test]
     fn remove_entry_works() {
         let mut index = SecretIndex::default();
         let t = Timestamp::now();
-        upsert_entry(&mut index, "ns", "sec1", t);
-        upsert_entry(&mut index, "ns", "sec2", t);
+        upsert_entry(&mut index, "ns", "sec1", &stored_at(t));
+        upsert_entry(&mut index, "ns", "sec2", &stored_at(t));
         remove_entry(&mut index, "ns", "sec1");
         assert_eq!(index.entries.len(), 1);
         assert_eq!(index.entries[0].secret, "sec2");
@@ -140,9 +283,9 @@ mod tests {
     fn filter_by_namespace() {
         let mut index = SecretIndex::default();
         let t = Timestamp::now();
-        upsert_entry(&mut index, "ns1", "sec1", t);
-        upsert_entry(&mut index, "ns2", "sec2", t);
-        upsert_entry(&mut index, "ns1", "sec3", t);
+        upsert_entry(&mut index, "ns1", "sec1", &stored_at(t));
+        upsert_entry(&mut index, "ns2", "sec2", &stored_at(t));
+        upsert_entry(&mut index, "ns1", "sec3", &stored_at(t));
 
         let filtered = filter_entries(&index, Some("ns1"));
         assert_eq!(filtered.len(), 2);
@@ -153,13 +296,99 @@ mod tests {
     fn filter_no_namespace_returns_all() {
         let mut index = SecretIndex::default();
         let t = Timestamp::now();
-        upsert_entry(&mut index, "ns1", "sec1", t);
-        upsert_entry(&mut index, "ns2", "sec2", t);
+        upsert_entry(&mut index, "ns1", "sec1", &stored_at(t));
+        upsert_entry(&mut index, "ns2", "sec2", &stored_at(t));
 
         let filtered = filter_entries(&index, None);
         assert_eq!(filtered.len(), 2);
     }
 
+    fn entry_expiring_at(expires_at: Option<Timestamp>) -> IndexEntry {
+        IndexEntry {
+            namespace: "ns".into(),
+            secret: "sec".into(),
+            created_at: Timestamp::from_second(0).unwrap(),
+            ttl_seconds: None,
+            expires_at,
+            source_type: None,
+        }
+    }
+
+    #[test]
+    fn expiry_filters() {
+        let now = Timestamp::from_second(10_000).unwrap();
+        let expired = entry_expiring_at(Some(Timestamp::from_second(9_000).unwrap()));
+        let soon = entry_expiring_at(Some(Timestamp::from_second(10_600).unwrap()));
+        let later = entry_expiring_at(Some(Timestamp::from_second(20_000).unwrap()));
+        let never = entry_expiring_at(None);
+        let hour = ExpiryFilter::ExpiringWithin(SignedDuration::from_hours(1));
+
+        for entry in [&expired, &soon, &later, &never] {
+            assert!(ExpiryFilter::All.matches(entry, now));
+        }
+
+        assert!(ExpiryFilter::Expired.matches(&expired, now));
+        assert!(!ExpiryFilter::Expired.matches(&soon, now));
+        assert!(!ExpiryFilter::Expired.matches(&never, now));
+
+        assert!(!ExpiryFilter::Valid.matches(&expired, now));
+        assert!(ExpiryFilter::Valid.matches(&soon, now));
+        assert!(ExpiryFilter::Valid.matches(&never, now));
+
+        assert!(!hour.matches(&expired, now));
+        assert!(hour.matches(&soon, now));
+        assert!(!hour.matches(&later, now));
+        assert!(!hour.matches(&never, now));
+    }
+
+    #[test]
+    fn corrupt_index_loads_as_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        fs::write(&path, r#"{"entries":[{"namesp"#).unwrap();
+        let index = load_index(&path).unwrap();
+        assert!(index.entries.is_empty());
+    }
+
+    #[test]
+    fn update_replaces_corrupt_index_and_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        fs::write(&path, "garbage").unwrap();
+
+        update_index(&path, |idx| {
+            upsert_entry(idx, "ns", "sec", &stored_at(Timestamp::now()));
+        })
+        .unwrap();
+
+        assert_eq!(load_index(&path).unwrap().entries.len(), 1);
+        let backup = dir.path().join("index.json.corrupt");
+        assert_eq!(fs::read_to_string(backup).unwrap(), "garbage");
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let path = &path;
+                scope.spawn(move || {
+                    for j in 0..5 {
+                        update_index(path, |idx| {
+                            let name = format!("sec-{i}-{j}");
+                            upsert_entry(idx, "ns", &name, &stored_at(Timestamp::now()));
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(load_index(&path).unwrap().entries.len(), 40);
+    }
+
     #[test]
     fn parent_directory_creation() {
         let dir = tempfile::tempdir().unwrap();
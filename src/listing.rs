@@ -0,0 +1,421 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use jiff::Timestamp;
+use serde::Serialize;
+
+use crate::duration;
+use crate::index::IndexEntry;
+use crate::model::SourceType;
+
+/// Output format for `hemli list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListFormat {
+    /// Tab-separated namespace, secret, creation time, status, and TTL
+    Tsv,
+    /// Aligned columns with a header row
+    Table,
+    /// JSON array with all indexed metadata
+    Json,
+    /// One line per secret from a template such as `{namespace}/{secret}`
+    Template(Vec<Segment>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Namespace,
+    Secret,
+    CreatedAt,
+    /// The TTL as a duration such as `12h`
+    Ttl,
+    TtlSeconds,
+    ExpiresAt,
+    Status,
+    SourceType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+impl Field {
+    const ALL: [Field; 8] = [
+        Field::Namespace,
+        Field::Secret,
+        Field::CreatedAt,
+        Field::Ttl,
+        Field::TtlSeconds,
+        Field::ExpiresAt,
+        Field::Status,
+        Field::SourceType,
+    ];
+
+    /// Columns of the table format
+    const TABLE: [Field; 7] = [
+        Field::Namespace,
+        Field::Secret,
+        Field::CreatedAt,
+        Field::Ttl,
+        Field::ExpiresAt,
+        Field::Status,
+        Field::SourceType,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Namespace => "namespace",
+            Field::Secret => "secret",
+            Field::CreatedAt => "created_at",
+            Field::Ttl => "ttl",
+            Field::TtlSeconds => "ttl_seconds",
+            Field::ExpiresAt => "expires_at",
+            Field::Status => "status",
+            Field::SourceType => "source_type",
+        }
+    }
+
+    /// Rendered value, or `None` if the entry has no value for this field.
+    fn value(self, entry: &IndexEntry, now: Timestamp) -> Option<String> {
+        match self {
+            Field::Namespace => Some(entry.namespace.clone()),
+            Field::Secret => Some(entry.secret.clone()),
+            Field::CreatedAt => Some(entry.created_at.to_string()),
+            Field::Ttl => entry.ttl_seconds.map(duration::format_seconds),
+            Field::TtlSeconds => entry.ttl_seconds.map(|ttl| ttl.to_string()),
+            Field::ExpiresAt => entry.expires_at.map(|exp| exp.to_string()),
+            Field::Status => Some(status(entry, now).to_string()),
+            Field::SourceType => entry.source_type.map(|st| source_type_name(st).to_string()),
+        }
+    }
+}
+
+fn status(entry: &IndexEntry, now: Timestamp) -> &'static str {
+    if entry.is_expired_at(now) {
+        "expired"
+    } else {
+        "valid"
+    }
+}
+
+fn source_type_name(source_type: SourceType) -> &'static str {
+    match source_type {
+        SourceType::Sh => "sh",
+        SourceType::Cmd => "cmd",
+    }
+}
+
+impl FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(ListFormat::Tsv),
+            "table" => Ok(ListFormat::Table),
+            "json" => Ok(ListFormat::Json),
+            _ if s.contains('{') => parse_template(s).map(ListFormat::Template),
+            _ => Err(format!(
+                "unknown format '{s}'; expected tsv, table, json, or a template like \
+                 '{{namespace}}/{{secret}}'"
+            )),
+        }
+    }
+}
+
+/// Split a template into literals and `{field}` placeholders. `{{` and `}}`
+/// produce literal braces.
+fn parse_template(template: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                if !closed {
+                    return Err("unclosed '{' in template".into());
+                }
+                let field = Field::ALL
+                    .into_iter()
+                    .find(|f| f.name() == name)
+                    .ok_or_else(|| {
+                        let known: Vec<_> = Field::ALL.iter().map(|f| f.name()).collect();
+                        format!(
+                            "unknown placeholder '{{{name}}}'; expected one of: {}",
+                            known.join(", ")
+                        )
+                    })?;
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Field(field));
+            }
+            '}' => return Err("unmatched '}' in template; use '}}' for a literal brace".into()),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    namespace: &'a str,
+    secret: &'a str,
+    created_at: Timestamp,
+    ttl: Option<String>,
+    ttl_seconds: Option<i64>,
+    expires_at: Option<Timestamp>,
+    status: &'static str,
+    source_type: Option<SourceType>,
+}
+
+pub fn render(
+    entries: &[&IndexEntry],
+    format: &ListFormat,
+    now: Timestamp,
+) -> Result<String, serde_json::Error> {
+    let mut out = String::new();
+    match format {
+        ListFormat::Tsv => {
+            // New columns go at the end, so scripts cutting the first ones
+            // keep working
+            for entry in entries {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}",
+                    entry.namespace,
+                    entry.secret,
+                    entry.created_at,
+                    status(entry, now),
+                    Field::Ttl.value(entry, now).unwrap_or_default()
+                )
+                .unwrap();
+            }
+        }
+        ListFormat::Table => render_table(&mut out, entries, now),
+        ListFormat::Json => {
+            let json: Vec<_> = entries
+                .iter()
+                .map(|e| JsonEntry {
+                    namespace: &e.namespace,
+                    secret: &e.secret,
+                    created_at: e.created_at,
+                    ttl: e.ttl_seconds.map(duration::format_seconds),
+                    ttl_seconds: e.ttl_seconds,
+                    expires_at: e.expires_at,
+                    status: status(e, now),
+                    source_type: e.source_type,
+                })
+                .collect();
+            out = serde_json::to_string_pretty(&json)?;
+            out.push('\n');
+        }
+        ListFormat::Template(segments) => {
+            for entry in entries {
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(text) => out.push_str(text),
+                        Segment::Field(field) => {
+                            out.push_str(&field.value(entry, now).unwrap_or_default());
+                        }
+                    }
+                }
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn render_table(out: &mut String, entries: &[&IndexEntry], now: Timestamp) {
+    let header: Vec<String> = Field::TABLE
+        .iter()
+        .map(|f| f.name().to_uppercase())
+        .collect();
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|e| {
+            Field::TABLE
+                .iter()
+                .map(|f| f.value(e, now).unwrap_or_else(|| "-".into()))
+                .collect()
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(String::len).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{cell:width$}"))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> Timestamp {
+        "2025-01-15T11:00:00Z".parse().unwrap()
+    }
+
+    fn entry(namespace: &str, secret: &str) -> IndexEntry {
+        IndexEntry {
+            namespace: namespace.into(),
+            secret: secret.into(),
+            created_at: "2025-01-15T10:30:00Z".parse().unwrap(),
+            ttl_seconds: None,
+            expires_at: None,
+            source_type: None,
+        }
+    }
+
+    fn entry_with_ttl(namespace: &str, secret: &str) -> IndexEntry {
+        IndexEntry {
+            ttl_seconds: Some(3600),
+            expires_at: Some("2025-01-15T11:30:00Z".parse().unwrap()),
+            source_type: Some(SourceType::Sh),
+            ..entry(namespace, secret)
+        }
+    }
+
+    #[test]
+    fn parse_named_formats() {
+        assert_eq!("tsv".parse::<ListFormat>().unwrap(), ListFormat::Tsv);
+        assert_eq!("table".parse::<ListFormat>().unwrap(), ListFormat::Table);
+        assert_eq!("json".parse::<ListFormat>().unwrap(), ListFormat::Json);
+    }
+
+    #[test]
+    fn parse_unknown_format_errors() {
+        assert!("yaml".parse::<ListFormat>().is_err());
+    }
+
+    #[test]
+    fn parse_template_segments() {
+        let format: ListFormat = "{namespace}/{secret}".parse().unwrap();
+        assert_eq!(
+            format,
+            ListFormat::Template(vec![
+                Segment::Field(Field::Namespace),
+                Segment::Literal("/".into()),
+                Segment::Field(Field::Secret),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_template_unknown_placeholder_errors() {
+        let err = "{namespace}/{nope}".parse::<ListFormat>().unwrap_err();
+        assert!(err.contains("{nope}"));
+    }
+
+    #[test]
+    fn parse_template_unclosed_errors() {
+        assert!("{namespace".parse::<ListFormat>().is_err());
+        assert!("{namespace}}".parse::<ListFormat>().is_err());
+    }
+
+    #[test]
+    fn parse_template_escaped_braces() {
+        let format: ListFormat = "{{{secret}}}".parse().unwrap();
+        let out = render(&[&entry("ns", "sec")], &format, now()).unwrap();
+        assert_eq!(out, "{sec}\n");
+    }
+
+    #[test]
+    fn render_tsv() {
+        let entries = [&entry("ns", "sec"), &entry_with_ttl("ns", "leased")];
+        let out = render(&entries, &ListFormat::Tsv, now()).unwrap();
+        assert_eq!(
+            out,
+            "ns\tsec\t2025-01-15T10:30:00Z\tvalid\t\n\
+             ns\tleased\t2025-01-15T10:30:00Z\tvalid\t1h\n"
+        );
+    }
+
+    #[test]
+    fn render_template_missing_fields_are_empty() {
+        let format: ListFormat = "{secret}:{ttl}:{ttl_seconds}:{source_type}"
+            .parse()
+            .unwrap();
+        let plain = entry("ns", "a");
+        let with_ttl = entry_with_ttl("ns", "b");
+        let out = render(&[&plain, &with_ttl], &format, now()).unwrap();
+        assert_eq!(out, "a:::\nb:1h:3600:sh\n");
+    }
+
+    #[test]
+    fn render_json_includes_metadata() {
+        let out = render(&[&entry_with_ttl("ns", "sec")], &ListFormat::Json, now()).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json[0]["namespace"], "ns");
+        assert_eq!(json[0]["ttl"], "1h");
+        assert_eq!(json[0]["ttl_seconds"], 3600);
+        assert_eq!(json[0]["expires_at"], "2025-01-15T11:30:00Z");
+        assert_eq!(json[0]["source_type"], "sh");
+        assert_eq!(json[0]["status"], "valid");
+    }
+
+    #[test]
+    fn render_status() {
+        let format: ListFormat = "{secret}={status}".parse().unwrap();
+        let never = entry("ns", "never");
+        let valid = entry_with_ttl("ns", "valid");
+        let later: Timestamp = "2025-01-15T12:00:00Z".parse().unwrap();
+        let out = render(&[&never, &valid], &format, now()).unwrap();
+        assert_eq!(out, "never=valid\nvalid=valid\n");
+        let out = render(&[&never, &valid], &format, later).unwrap();
+        assert_eq!(out, "never=valid\nvalid=expired\n");
+    }
+
+    #[test]
+    fn render_json_empty_is_array() {
+        let out = render(&[], &ListFormat::Json, now()).unwrap();
+        assert_eq!(out.trim(), "[]");
+    }
+
+    #[test]
+    fn render_table_aligns_columns() {
+        let short = entry("ns", "a");
+        let long = entry_with_ttl("namespace", "secret");
+        let out = render(&[&short, &long], &ListFormat::Table, now()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("NAMESPACE  SECRET"));
+        assert!(lines[0].contains(" TTL "));
+        assert!(lines[2].contains(" 1h "));
+        let col = lines[0].find("CREATED_AT").unwrap();
+        assert_eq!(&lines[1][col..col + 20], "2025-01-15T10:30:00Z");
+        assert_eq!(&lines[2][col..col + 20], "2025-01-15T10:30:00Z");
+        assert!(lines[1].ends_with('-'));
+    }
+}
@@ -1,22 +1,55 @@
+#[cfg(unix)]
+mod agent;
 mod cli;
+mod doctor;
+mod duration;
 mod error;
+mod expiry;
+mod field;
+mod fsutil;
 mod index;
+mod listing;
+mod manifest;
 mod model;
+mod refresh;
 mod source;
 mod store;
 
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
 use anyhow::Result;
 use clap::CommandFactory;
 use clap::Parser;
 use clap_complete::generate;
+use jiff::SignedDuration;
+use jiff::Timestamp;
+use serde::Serialize;
 use tracing::debug;
 use tracing_subscriber::EnvFilter;
 
 use crate::cli::Cli;
 use crate::cli::Command;
 use crate::error::HemliError;
+use crate::fsutil::FileLock;
+use crate::index::ExpiryFilter;
+use crate::listing::ListFormat;
+use crate::manifest::Manifest;
+use crate::model::DailyExpiry;
+use crate::model::OutputMode;
+use crate::model::RetryPolicy;
+use crate::model::SourceCommand;
 use crate::model::SourceType;
+use crate::model::StaleIfError;
 use crate::model::StoredSecret;
+use crate::model::TtlFrom;
+use crate::refresh::RefreshOutcome;
+use crate::store::Backend;
+use crate::store::SecretStore;
 
 fn main() -> Result<()> {
     tracing_subscriber::fmt()
@@ -25,6 +58,12 @@ fn main() -> Result<()> {
         .init();
 
     let cli = Cli::parse();
+    let backend = cli.backend;
+    let store = store::open(backend);
+    let store = store.as_ref();
+    let default_source_timeout = cli
+        .default_source_timeout
+        .map(|timeout| Duration::from_secs(duration::ceil_seconds(timeout)));
 
     match cli.command {
         Command::Get {
@@ -36,23 +75,127 @@ fn main() -> Result<()> {
             ttl,
             source_sh,
             source_cmd,
-        } => cmd_get(
-            &namespace,
-            &secret,
-            force_refresh,
-            no_refresh,
-            no_store,
-            ttl,
-            source_sh,
-            source_cmd,
+            source_argv,
+            source_timeout,
+            retries,
+            retry_backoff,
+            stale_if_error,
+            output_mode,
+            field,
+            fields,
+            ttl_from,
+            ttl_margin,
+            refresh_ahead,
+            expires_at,
+            expires_daily_at,
+        } => {
+            let manifest = project_manifest();
+            let namespace = resolve_namespace(namespace, manifest.as_ref())?;
+            cmd_get(
+                store,
+                &namespace,
+                &secret,
+                ResolveOptions {
+                    force_refresh,
+                    no_refresh,
+                    no_store,
+                    ttl: ttl.map(duration::ttl_seconds),
+                    source_sh,
+                    source_cmd: cli::source_cmd_argv(source_cmd, source_argv),
+                    source_timeout_seconds: source_timeout.map(duration::ceil_seconds),
+                    default_source_timeout,
+                    retry: retry_policy(retries, retry_backoff),
+                    stale_if_error: stale_if_error.map(stale_policy),
+                    output_mode,
+                    field,
+                    fields: fields.into_iter().collect(),
+                    ttl_from,
+                    ttl_margin_seconds: ttl_margin.map(duration::ceil_seconds),
+                    refresh_ahead_seconds: refresh_ahead.map(duration::ceil_seconds),
+                    absolute_expiry: expires_at,
+                    daily_expiry: expires_daily_at,
+                    backend,
+                    manifest: manifest.as_ref(),
+                },
+            )?
+        }
+        Command::Run {
+            namespace,
+            env,
+            command,
+        } => {
+            let manifest = project_manifest();
+            let namespace = resolve_namespace(namespace, manifest.as_ref())?;
+            let opts = ResolveOptions {
+                default_source_timeout,
+                backend,
+                manifest: manifest.as_ref(),
+                ..Default::default()
+            };
+            cmd_run(store, &namespace, &env, &command, &opts)?
+        }
+        Command::Refresh {
+            namespace,
+            secrets,
+            all,
+            expired,
+            jobs,
+        } => cmd_refresh(
+            store,
+            namespace.as_deref(),
+            &secrets,
+            all,
+            expired,
+            jobs.into(),
+            default_source_timeout,
         )?,
+        Command::Purge {
+            namespace,
+            expired,
+            older_than,
+            dry_run,
+        } => cmd_purge(store, namespace.as_deref(), expired, older_than, dry_run)?,
+        Command::Doctor { fix } => cmd_doctor(store, fix)?,
+        Command::Agent {
+            status,
+            stop,
+            idle_timeout,
+        } => {
+            let idle_timeout =
+                idle_timeout.map(|timeout| Duration::from_secs(duration::ceil_seconds(timeout)));
+            cmd_agent(backend, default_source_timeout, idle_timeout, status, stop)?
+        }
+        Command::Lock => cmd_lock(backend)?,
+        Command::Unlock => cmd_unlock(backend)?,
         Command::Completions { shell } => {
             let mut cmd = Cli::command();
             generate(shell, &mut cmd, "hemli", &mut std::io::stdout());
         }
-        Command::Delete { namespace, secret } => cmd_delete(&namespace, &secret)?,
-        Command::List { namespace } => cmd_list(namespace.as_deref())?,
-        Command::Inspect { namespace, secret } => cmd_inspect(&namespace, &secret)?,
+        Command::Set {
+            namespace,
+            secret,
+            ttl,
+        } => cmd_set(store, &namespace, &secret, ttl.map(duration::ttl_seconds))?,
+        Command::Delete { namespace, secret } => cmd_delete(store, &namespace, &secret)?,
+        Command::List {
+            namespace,
+            format,
+            expired,
+            valid,
+            expiring_within,
+        } => {
+            let filter = if expired {
+                ExpiryFilter::Expired
+            } else if valid {
+                ExpiryFilter::Valid
+            } else if let Some(window) = expiring_within {
+                ExpiryFilter::ExpiringWithin(window)
+            } else {
+                ExpiryFilter::All
+            };
+            cmd_list(namespace.as_deref(), &format, filter)?
+        }
+        Command::Inspect { namespace, secret } => cmd_inspect(store, &namespace, &secret)?,
         Command::Edit {
             namespace,
             secret,
@@ -60,105 +203,827 @@ fn main() -> Result<()> {
             clear_ttl,
             source_sh,
             source_cmd,
-        } => cmd_edit(&namespace, &secret, ttl, clear_ttl, source_sh, source_cmd)?,
+            source_argv,
+            source_timeout,
+            clear_source_timeout,
+            retries,
+            retry_backoff,
+            clear_retries,
+            stale_if_error,
+            clear_stale_if_error,
+            output_mode,
+            field,
+            clear_field,
+            fields,
+            clear_fields,
+            ttl_from,
+            ttl_margin,
+            clear_ttl_from,
+            refresh_ahead,
+            clear_refresh_ahead,
+            expires_at,
+            clear_expires_at,
+            expires_daily_at,
+            clear_expires_daily_at,
+        } => cmd_edit(
+            store,
+            &namespace,
+            &secret,
+            EditOptions {
+                ttl: ttl.map(duration::ttl_seconds),
+                clear_ttl,
+                source_sh,
+                source_cmd: cli::source_cmd_argv(source_cmd, source_argv),
+                source_timeout_seconds: source_timeout.map(duration::ceil_seconds),
+                clear_source_timeout,
+                retry: retry_policy(retries, retry_backoff),
+                clear_retries,
+                stale_if_error: stale_if_error.map(stale_policy),
+                clear_stale_if_error,
+                output_mode,
+                field,
+                clear_field,
+                fields: fields.into_iter().collect(),
+                clear_fields,
+                ttl_from,
+                ttl_margin_seconds: ttl_margin.map(duration::ceil_seconds),
+                clear_ttl_from,
+                refresh_ahead_seconds: refresh_ahead.map(duration::ceil_seconds),
+                clear_refresh_ahead,
+                absolute_expiry: expires_at,
+                clear_absolute_expiry: clear_expires_at,
+                daily_expiry: expires_daily_at,
+                clear_daily_expiry: clear_expires_daily_at,
+            },
+        )?,
     }
-
     Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
-fn cmd_get(
-    namespace: &str,
-    secret: &str,
+/// Cache and source settings for resolving a single secret.
+#[derive(Debug, Default, Clone)]
+struct ResolveOptions<'a> {
     force_refresh: bool,
     no_refresh: bool,
     no_store: bool,
     ttl: Option<i64>,
     source_sh: Option<String>,
-    source_cmd: Option<String>,
+    source_cmd: Option<Vec<String>>,
+    /// Time limit stored with the secret
+    source_timeout_seconds: Option<u64>,
+    /// Time limit for secrets that have none of their own
+    default_source_timeout: Option<Duration>,
+    /// Retry policy stored with the secret
+    retry: Option<RetryPolicy>,
+    /// Stale-if-error policy stored with the secret
+    stale_if_error: Option<StaleIfError>,
+    /// How source output becomes the value, stored with the secret
+    output_mode: Option<OutputMode>,
+    /// JSON field to extract from source output, stored with the secret
+    field: Option<String>,
+    /// Named JSON fields to extract, stored with the secret
+    fields: BTreeMap<String, String>,
+    /// Where to read the expiry from, stored with the secret
+    ttl_from: Option<TtlFrom>,
+    ttl_margin_seconds: Option<u64>,
+    /// Background refresh window before expiry, stored with the secret
+    refresh_ahead_seconds: Option<u64>,
+    /// Fixed and daily expiry times, stored with the secret
+    absolute_expiry: Option<Timestamp>,
+    daily_expiry: Option<DailyExpiry>,
+    /// Backend for background refreshes to use
+    backend: Backend,
+    manifest: Option<&'a Manifest>,
+}
+
+/// The stale-if-error policy for `--stale-if-error[=MAX_AGE]`.
+fn stale_policy(max_age: Option<SignedDuration>) -> StaleIfError {
+    StaleIfError {
+        max_age_seconds: max_age.map(duration::ceil_seconds),
+    }
+}
+
+/// The retry policy for `--retries` and `--retry-backoff`, if given.
+fn retry_policy(retries: Option<u32>, backoff: Option<SignedDuration>) -> Option<RetryPolicy> {
+    retries.map(|retries| RetryPolicy {
+        retries,
+        backoff_ms: backoff
+            .map(|b| u64::try_from(b.as_millis()).unwrap_or(u64::MAX))
+            .unwrap_or(RetryPolicy::DEFAULT_BACKOFF_MS),
+    })
+}
+
+/// The nearest project manifest, if there is one. A manifest that cannot be
+/// read is only warned about, so that it stands in the way of nothing but
+/// the fallbacks it would have provided.
+fn project_manifest() -> Option<Manifest> {
+    let found = std::env::current_dir()
+        .map_err(HemliError::from)
+        .and_then(|dir| manifest::discover(&dir));
+    match found {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("warning: {e}; ignoring it");
+            None
+        }
+    }
+}
+
+/// The namespace from the command line or environment, falling back to the one
+/// declared in the project manifest.
+fn resolve_namespace(
+    namespace: Option<String>,
+    manifest: Option<&Manifest>,
+) -> Result<String, HemliError> {
+    namespace
+        .or_else(|| manifest.and_then(|m| m.namespace.clone()))
+        .ok_or(HemliError::NoNamespace)
+}
+
+fn cmd_get(
+    store: &dyn SecretStore,
+    namespace: &str,
+    secret: &str,
+    opts: ResolveOptions,
 ) -> Result<()> {
-    let existing = store::get_secret(namespace, secret)?;
+    let value = resolve_secret(store, namespace, secret, opts)?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&value)?;
+    stdout.flush()?;
+    Ok(())
+}
 
-    if no_refresh {
-        match existing {
-            Some(entry) => {
-                print!("{}", entry.value);
-                return Ok(());
-            }
-            None => {
-                return Err(HemliError::NotFound {
-                    namespace: namespace.to_string(),
-                    secret: secret.to_string(),
-                }
-                .into());
+/// Return the cached value of a secret, fetching it from its source first if
+/// it is missing, expired, or a refresh is forced.
+///
+/// `name` is either a secret name or `secret#field` for one of the secret's
+/// named fields.
+fn resolve_secret(
+    store: &dyn SecretStore,
+    namespace: &str,
+    name: &str,
+    opts: ResolveOptions,
+) -> Result<Vec<u8>> {
+    let (secret, field, existing) = split_field(store, namespace, name, !opts.fields.is_empty())?;
+
+    if opts.no_refresh {
+        return match existing {
+            Some(entry) => Ok(entry.select(field)?),
+            None => Err(HemliError::NotFound {
+                namespace: namespace.to_string(),
+                secret: secret.to_string(),
             }
-        }
+            .into()),
+        };
     }
 
-    let needs_refresh =
-        force_refresh || existing.is_none() || existing.as_ref().is_some_and(|e| e.is_expired());
+    let needs_refresh = opts.force_refresh
+        || existing.is_none()
+        || existing.as_ref().is_some_and(|e| e.is_expired());
 
     if !needs_refresh {
         let entry = existing.unwrap();
         debug!("returning cached secret");
-        print!("{}", entry.value);
-        return Ok(());
+        if !opts.no_store && entry.is_due_for_refresh(Timestamp::now()) {
+            spawn_background_refresh(namespace, secret, &entry, &opts);
+        }
+        return Ok(entry.select(field)?);
     }
 
-    // Determine source: CLI args take priority, fall back to stored source
-    let (cmd_str, src_type) = if let Some(ref sh) = source_sh {
-        (sh.clone(), SourceType::Sh)
-    } else if let Some(ref cmd) = source_cmd {
-        (cmd.clone(), SourceType::Cmd)
-    } else if let Some(ref entry) = existing {
-        match (&entry.source_command, &entry.source_type) {
-            (Some(cmd), Some(st)) => (cmd.clone(), *st),
-            _ => return Err(HemliError::NoSource.into()),
+    // Only one process fetches a given secret at a time
+    let lock_path = fsutil::fetch_lock_path(namespace, secret);
+    let _fetch_lock = match lock_fetch(store, namespace, secret, &lock_path, opts.force_refresh)? {
+        FetchLock::Acquired(lock) => lock,
+        FetchLock::Fetched(entry) => {
+            debug!("returning secret fetched by another process");
+            return Ok(entry.select(field)?);
         }
+    };
+
+    let declared = opts.manifest.and_then(|m| m.secret(namespace, secret));
+
+    // Determine source: CLI args take priority, then the stored source, then
+    // the project manifest
+    let stored_source = existing
+        .as_ref()
+        .and_then(|e| e.source_command.clone().zip(e.source_type));
+    let (command, src_type) = if let Some(sh) = opts.source_sh {
+        (SourceCommand::Line(sh), SourceType::Sh)
+    } else if let Some(argv) = opts.source_cmd {
+        (SourceCommand::Argv(argv), SourceType::Cmd)
+    } else if let Some(source) = stored_source {
+        source
+    } else if let Some(source) = declared.and_then(|d| d.source()) {
+        debug!("using source declared in manifest");
+        source
     } else {
         return Err(HemliError::NoSource.into());
     };
 
-    debug!(command = %cmd_str, source_type = ?src_type, "fetching secret from source");
-    let value = source::fetch_secret(&cmd_str, &src_type)?;
+    // Determine TTL: CLI arg takes priority, fall back to existing entry's TTL,
+    // then the manifest
+    let effective_ttl = opts
+        .ttl
+        .or_else(|| existing.as_ref().and_then(|e| e.ttl_seconds))
+        .or_else(|| declared.and_then(|d| d.ttl));
+
+    // Fetch settings from the command line replace the stored ones
+    let previous = existing.as_ref();
+    let mut stored = StoredSecret::new(
+        String::new(),
+        Some(command.clone()),
+        Some(src_type),
+        effective_ttl,
+    );
+    stored.source_timeout_seconds = opts
+        .source_timeout_seconds
+        .or_else(|| previous.and_then(|e| e.source_timeout_seconds));
+    stored.retry = opts.retry.or_else(|| previous.and_then(|e| e.retry));
+    stored.stale_if_error = opts
+        .stale_if_error
+        .or_else(|| previous.and_then(|e| e.stale_if_error));
+    stored.output_mode = opts
+        .output_mode
+        .or_else(|| previous.and_then(|e| e.output_mode));
+    stored.field = opts
+        .field
+        .or_else(|| previous.and_then(|e| e.field.clone()));
+    stored.fields = if opts.fields.is_empty() {
+        previous.map(|e| e.fields.clone()).unwrap_or_default()
+    } else {
+        opts.fields
+    };
+    stored.ttl_from = opts
+        .ttl_from
+        .or_else(|| previous.and_then(|e| e.ttl_from.clone()));
+    stored.ttl_margin_seconds = opts
+        .ttl_margin_seconds
+        .or_else(|| previous.and_then(|e| e.ttl_margin_seconds));
+    stored.refresh_ahead_seconds = opts
+        .refresh_ahead_seconds
+        .or_else(|| previous.and_then(|e| e.refresh_ahead_seconds));
+    stored.absolute_expiry = opts
+        .absolute_expiry
+        .or_else(|| previous.and_then(|e| e.absolute_expiry));
+    stored.daily_expiry = opts
+        .daily_expiry
+        .or_else(|| previous.and_then(|e| e.daily_expiry.clone()));
+    let fetch_opts = refresh::fetch_options(&stored, opts.default_source_timeout);
+
+    debug!(
+        ?command,
+        source_type = ?src_type,
+        timeout = ?fetch_opts.timeout,
+        "fetching secret from source"
+    );
+    let fetched = match source::fetch_secret(&command, &src_type, &fetch_opts) {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            // Fall back to the cached value if the secret allows it
+            let now = Timestamp::now();
+            return match existing {
+                Some(entry)
+                    if stored
+                        .stale_if_error
+                        .is_some_and(|p| p.allows(entry.expires_at, now)) =>
+                {
+                    // A forced or early refresh may fail while the value is
+                    // still valid
+                    let expiry = match entry.expires_at {
+                        Some(exp) if now > exp => format!("expired {exp}"),
+                        Some(exp) => format!("expires {exp}"),
+                        None => "no expiry".to_string(),
+                    };
+                    eprintln!(
+                        "warning: refreshing {namespace}/{secret} failed ({e}); using cached value ({expiry})"
+                    );
+                    Ok(entry.select(field)?)
+                }
+                _ => Err(e.into()),
+            };
+        }
+    };
+    let stored = refresh::apply_fetched(namespace, secret, &stored, fetched);
+
+    if !opts.no_store {
+        store.set(namespace, secret, &stored)?;
+        index::record_secret(namespace, secret, &stored)?;
+        debug!("stored secret in backend and index");
+    }
+
+    Ok(stored.select(field)?)
+}
+
+/// Split `name` into a secret, one of its fields and the stored secret.
+///
+/// `secret#field` names a field if `secret` exists or is being given fields
+/// by `with_fields`, unless a secret is stored under the whole name, as one
+/// could be before names had fields. Any other name with `#` is rejected.
+fn split_field<'a>(
+    store: &dyn SecretStore,
+    namespace: &str,
+    name: &'a str,
+    with_fields: bool,
+) -> Result<(&'a str, Option<&'a str>, Option<StoredSecret>), HemliError> {
+    let Some((secret, field)) = name.split_once('#') else {
+        return Ok((name, None, store.get(namespace, name)?));
+    };
+    if let Some(whole) = store.get(namespace, name)? {
+        return Ok((name, None, Some(whole)));
+    }
+    let existing = store.get(namespace, secret)?;
+    if existing.is_none() && !with_fields {
+        return Err(HemliError::InvalidName(name.to_string()));
+    }
+    Ok((secret, Some(field), existing))
+}
+
+/// The outcome of [`lock_fetch`].
+enum FetchLock {
+    /// This process holds the fetch lock and should run the source.
+    Acquired(FileLock),
+    /// Another process stored a valid value while this one was getting there.
+    Fetched(Box<StoredSecret>),
+}
 
-    // Determine TTL: CLI arg takes priority, fall back to existing entry's TTL
-    let effective_ttl = ttl.or_else(|| existing.as_ref().and_then(|e| e.ttl_seconds));
+/// Take the fetch lock of a secret, then re-read the store. Another process
+/// may have stored a fresh value since the caller last read it, whether or
+/// not this one had to wait for the lock, and that value is returned instead
+/// of fetching again. A forced refresh always fetches.
+fn lock_fetch(
+    store: &dyn SecretStore,
+    namespace: &str,
+    secret: &str,
+    lock_path: &Path,
+    force_refresh: bool,
+) -> Result<FetchLock, HemliError> {
+    let lock = match FileLock::try_acquire(lock_path)? {
+        Some(lock) => lock,
+        None => {
+            debug!("waiting for another process to fetch this secret");
+            FileLock::acquire(lock_path)?
+        }
+    };
+    if !force_refresh
+        && let Some(entry) = store.get(namespace, secret)?
+        && !entry.is_expired()
+    {
+        return Ok(FetchLock::Fetched(Box::new(entry)));
+    }
+    Ok(FetchLock::Acquired(lock))
+}
+
+/// Start a detached `hemli refresh` of a secret that is about to expire,
+/// unless it has no stored source to refresh from, another process is already
+/// fetching it or has started a refresh, or the refresh would have to ask for
+/// a passphrase. The caller
+/// already has a valid value, so failures are only logged.
+fn spawn_background_refresh(
+    namespace: &str,
+    secret: &str,
+    entry: &StoredSecret,
+    opts: &ResolveOptions,
+) {
+    // `hemli refresh` only ever runs the stored source
+    if entry.source_command.is_none() || entry.source_type.is_none() {
+        debug!("secret has no stored source; not refreshing ahead");
+        return;
+    }
+    if !store::opens_unattended(opts.backend) {
+        debug!("store needs a passphrase prompt; not refreshing ahead");
+        return;
+    }
+    let lock_path = fsutil::fetch_lock_path(namespace, secret);
+    match FileLock::try_acquire(&lock_path) {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            debug!("secret is already being fetched; not refreshing ahead");
+            return;
+        }
+        Err(e) => {
+            debug!(error = %e, "could not check fetch lock; not refreshing ahead");
+            return;
+        }
+    }
+    // The lock is free again until the refresh takes it, so keep other gets
+    // from starting refreshes of their own meanwhile
+    match fsutil::mark_refresh_pending(&lock_path) {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("a background refresh is already pending; not refreshing ahead");
+            return;
+        }
+        Err(e) => {
+            debug!(error = %e, "could not mark refresh as pending; not refreshing ahead");
+            return;
+        }
+    }
 
-    let stored = StoredSecret::new(value.clone(), Some(cmd_str), Some(src_type), effective_ttl);
+    let secrets = [secret.to_string()];
+    let result = cli::refresh_command(
+        opts.backend,
+        opts.default_source_timeout,
+        namespace,
+        &secrets,
+    )
+    .and_then(|mut child| {
+        // Keep the refresh running if the caller's process group is
+        // interrupted, and without a controlling terminal, so that anything
+        // trying to prompt fails at once instead of stopping on SIGTTIN
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: the closure runs in the forked child before exec, where
+            // only async-signal-safe calls are allowed. It calls setsid and
+            // reads errno, both async-signal-safe, and neither allocates nor
+            // takes locks.
+            unsafe {
+                child.pre_exec(|| {
+                    if libc::setsid() == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        child.spawn()
+    });
+    match result {
+        Ok(child) => debug!(pid = child.id(), "refreshing secret ahead of expiry"),
+        Err(e) => {
+            debug!(error = %e, "could not start background refresh");
+            fsutil::clear_refresh_pending(&lock_path);
+        }
+    }
+}
 
-    if !no_store {
-        store::set_secret(namespace, secret, &stored)?;
+fn cmd_set(store: &dyn SecretStore, namespace: &str, secret: &str, ttl: Option<i64>) -> Result<()> {
+    let existing = store.get(namespace, secret)?;
+    // It could not be read back, as `#` introduces a field name
+    if existing.is_none() && secret.contains('#') {
+        return Err(HemliError::InvalidName(secret.to_string()).into());
+    }
 
-        let idx_path = index::index_path();
-        let mut idx = index::load_index(&idx_path)?;
-        index::upsert_entry(&mut idx, namespace, secret, stored.created_at);
-        index::save_index(&idx_path, &idx)?;
+    let stdin = std::io::stdin();
+    let value = if stdin.is_terminal() {
+        rpassword::prompt_password(format!("Value for '{secret}': "))?.into_bytes()
+    } else {
+        let mut input = Vec::new();
+        stdin.lock().read_to_end(&mut input)?;
+        if input.ends_with(b"\n") {
+            input.pop();
+            if input.ends_with(b"\r") {
+                input.pop();
+            }
+        }
+        input
+    };
+    if value.is_empty() {
+        return Err(HemliError::EmptyValue.into());
+    }
 
-        debug!("stored secret in keyring and index");
+    // An existing secret keeps its source and settings; values of its fields
+    // belonged to the value being replaced
+    let mut stored = match existing {
+        Some(existing) => {
+            let mut stored = existing.refreshed(value);
+            stored.field_values.clear();
+            stored
+        }
+        None => {
+            let mut stored = StoredSecret::new(String::new(), None, None, None);
+            stored.set_value_bytes(value);
+            stored
+        }
+    };
+    if let Some(ttl) = ttl {
+        stored.ttl_seconds = Some(ttl);
+        stored.recalculate_expires_at();
     }
+    store.set(namespace, secret, &stored)?;
+    index::record_secret(namespace, secret, &stored)?;
 
-    print!("{}", value);
+    eprintln!("Stored secret '{secret}' in namespace '{namespace}'");
     Ok(())
 }
 
-fn cmd_delete(namespace: &str, secret: &str) -> Result<()> {
-    store::delete_secret(namespace, secret)?;
+fn cmd_run(
+    store: &dyn SecretStore,
+    namespace: &str,
+    env: &[(String, String)],
+    command: &[String],
+    opts: &ResolveOptions,
+) -> Result<()> {
+    let mut child = std::process::Command::new(&command[0]);
+    child.args(&command[1..]);
+    for (var, secret) in env {
+        let value = resolve_secret(store, namespace, secret, opts.clone())?;
+        #[cfg(unix)]
+        let value = {
+            use std::os::unix::ffi::OsStringExt;
+            std::ffi::OsString::from_vec(value)
+        };
+        #[cfg(not(unix))]
+        let value = String::from_utf8_lossy(&value).into_owned();
+        child.env(var, value);
+    }
+    debug!(program = %command[0], vars = env.len(), "running command with secrets");
 
-    let idx_path = index::index_path();
-    let mut idx = index::load_index(&idx_path)?;
-    index::remove_entry(&mut idx, namespace, secret);
-    index::save_index(&idx_path, &idx)?;
+    let exec_error = |source| HemliError::Exec {
+        program: command[0].clone(),
+        source,
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Only returns on failure
+        Err(exec_error(child.exec()).into())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let status = child.status().map_err(exec_error)?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+fn cmd_delete(store: &dyn SecretStore, namespace: &str, secret: &str) -> Result<()> {
+    store.delete(namespace, secret)?;
+
+    index::update_index(&index::index_path(), |idx| {
+        index::remove_entry(idx, namespace, secret);
+    })?;
 
     eprintln!("Deleted secret '{secret}' from namespace '{namespace}'");
     Ok(())
 }
 
-fn cmd_inspect(namespace: &str, secret: &str) -> Result<()> {
-    let entry = store::get_secret(namespace, secret)?;
+fn cmd_refresh(
+    store: &dyn SecretStore,
+    namespace: Option<&str>,
+    secrets: &[String],
+    all: bool,
+    expired: bool,
+    jobs: usize,
+    default_source_timeout: Option<Duration>,
+) -> Result<()> {
+    let idx = index::load_index(&index::index_path())?;
+    // Unlock the store before taking any fetch lock, so that a passphrase
+    // prompt never holds up gets of the secrets being refreshed
+    store.list()?;
+
+    let targets: Vec<_> = index::filter_entries(&idx, namespace)
+        .into_iter()
+        .filter(|entry| secrets.is_empty() || secrets.contains(&entry.secret))
+        .map(|entry| (entry.namespace.clone(), entry.secret.clone()))
+        .collect();
+    let now = Timestamp::now();
+    let select = |stored: &StoredSecret| {
+        if all {
+            true
+        } else if expired {
+            stored.expires_at.is_some_and(|exp| now > exp)
+        } else {
+            stored.is_due_for_refresh(now)
+        }
+    };
+
+    let (mut total, mut failed, mut skipped) = (0, 0, 0);
+    for (namespace, secret, outcome) in
+        refresh::refresh_secrets(store, targets, select, jobs, default_source_timeout)
+    {
+        match outcome {
+            RefreshOutcome::Refreshed => {
+                total += 1;
+                eprintln!("refreshed {namespace}/{secret}");
+            }
+            RefreshOutcome::Failed(e) => {
+                total += 1;
+                failed += 1;
+                eprintln!("failed {namespace}/{secret}: {e}");
+            }
+            RefreshOutcome::Skipped(reason) => {
+                skipped += 1;
+                eprintln!("skipped {namespace}/{secret}: {reason}");
+            }
+        }
+    }
+
+    eprintln!(
+        "Refreshed {} of {total} secrets ({failed} failed, {skipped} skipped)",
+        total - failed
+    );
+    if failed > 0 {
+        return Err(HemliError::RefreshFailed { failed, total }.into());
+    }
+    Ok(())
+}
+
+fn cmd_purge(
+    store: &dyn SecretStore,
+    namespace: Option<&str>,
+    expired: bool,
+    older_than: Option<SignedDuration>,
+    dry_run: bool,
+) -> Result<()> {
+    let idx_path = index::index_path();
+    let idx = index::load_index(&idx_path)?;
+    let now = Timestamp::now();
+
+    let mut matching: Vec<(String, String)> = index::filter_entries(&idx, namespace)
+        .into_iter()
+        .filter(|e| !expired || e.is_expired_at(now))
+        .filter(|e| older_than.is_none_or(|age| now.duration_since(e.created_at) > age))
+        .map(|e| (e.namespace.clone(), e.secret.clone()))
+        .collect();
+
+    if dry_run {
+        for (ns, secret) in &matching {
+            println!("{ns}\t{secret}");
+        }
+        eprintln!("Would purge {} secrets", matching.len());
+        return Ok(());
+    }
+
+    let total = matching.len();
+    matching.retain(|(ns, secret)| match store.delete(ns, secret) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("failed {ns}/{secret}: {e}");
+            false
+        }
+    });
+    index::update_index(&idx_path, |idx| {
+        for (ns, secret) in &matching {
+            index::remove_entry(idx, ns, secret);
+        }
+    })?;
+
+    let failed = total - matching.len();
+    eprintln!("Purged {} of {total} secrets", matching.len());
+    if failed > 0 {
+        return Err(HemliError::PurgeFailed { failed, total }.into());
+    }
+    Ok(())
+}
+
+fn cmd_doctor(store: &dyn SecretStore, fix: bool) -> Result<()> {
+    let idx_path = index::index_path();
+    let idx = index::load_index(&idx_path)?;
+    let doctor::Diagnosis {
+        problems,
+        skipped_unindexed,
+    } = doctor::diagnose(store, &idx)?;
+
+    let fixed = if fix && !problems.is_empty() {
+        index::update_index(&idx_path, |idx| {
+            problems.iter().map(|p| doctor::fix(idx, p)).collect()
+        })?
+    } else {
+        vec![false; problems.len()]
+    };
+
+    let mut remaining = 0;
+    for (problem, fixed) in problems.iter().zip(fixed) {
+        if fixed {
+            eprintln!("fixed {problem}");
+        } else {
+            eprintln!("{problem}");
+            remaining += 1;
+        }
+    }
+
+    if skipped_unindexed {
+        eprintln!(
+            "note: the backend cannot list its secrets, so secrets missing from the index were not checked"
+        );
+    }
+
+    if remaining > 0 {
+        if !fix && problems.iter().any(|p| p.is_fixable()) {
+            eprintln!("Run 'hemli doctor --fix' to repair");
+        }
+        return Err(HemliError::Unhealthy(remaining).into());
+    }
+    eprintln!("{} entries checked, no problems remain", idx.entries.len());
+    Ok(())
+}
+
+/// Send `request` to the agent for `backend`, failing if none is running.
+#[cfg(unix)]
+fn agent_request(backend: Backend, request: &agent::Request) -> Result<agent::Response> {
+    let socket = agent::socket_path(backend);
+    agent::request(&socket, request)?.ok_or_else(|| {
+        HemliError::Agent(format!("no agent is listening on {}", socket.display())).into()
+    })
+}
+
+#[cfg(unix)]
+fn cmd_agent(
+    backend: Backend,
+    default_source_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    status: bool,
+    stop: bool,
+) -> Result<()> {
+    if status {
+        match agent_request(backend, &agent::Request::Status)? {
+            agent::Response::Status { pid, locked, .. } if locked => {
+                println!("agent running (pid {pid}), locked");
+            }
+            agent::Response::Status { pid, cached, .. } => {
+                println!("agent running (pid {pid}), {cached} secrets cached");
+            }
+            other => {
+                return Err(HemliError::Agent(format!("unexpected answer {other:?}")).into());
+            }
+        }
+    } else if stop {
+        agent_request(backend, &agent::Request::Stop)?;
+        eprintln!("Stopped agent");
+    } else {
+        if idle_timeout.is_some() {
+            ensure_lockable(backend)?;
+        }
+        agent::run(
+            store::open_direct(backend),
+            backend,
+            default_source_timeout,
+            idle_timeout,
+        )?;
+    }
+    Ok(())
+}
+
+/// Only the file backend can be locked: unlocking the keyring would take no
+/// passphrase, so it would not authenticate anyone.
+#[cfg(unix)]
+fn ensure_lockable(backend: Backend) -> Result<(), HemliError> {
+    match backend {
+        Backend::File => Ok(()),
+        Backend::Keyring => Err(HemliError::Agent(
+            "an agent for the keyring backend cannot be locked; lock the OS keyring instead".into(),
+        )),
+    }
+}
+
+#[cfg(unix)]
+fn cmd_lock(backend: Backend) -> Result<()> {
+    ensure_lockable(backend)?;
+    agent_request(backend, &agent::Request::Lock)?;
+    eprintln!("Locked agent");
+    Ok(())
+}
+
+#[cfg(unix)]
+fn cmd_unlock(backend: Backend) -> Result<()> {
+    ensure_lockable(backend)?;
+    let passphrase = Some(store::read_passphrase(false)?);
+    agent_request(backend, &agent::Request::Unlock { passphrase })?;
+    eprintln!("Unlocked agent");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn cmd_agent(
+    _backend: Backend,
+    _default_source_timeout: Option<Duration>,
+    _idle_timeout: Option<Duration>,
+    _status: bool,
+    _stop: bool,
+) -> Result<()> {
+    Err(HemliError::Agent("only supported on Unix".into()).into())
+}
+
+#[cfg(not(unix))]
+fn cmd_lock(_backend: Backend) -> Result<()> {
+    Err(HemliError::Agent("only supported on Unix".into()).into())
+}
+
+#[cfg(not(unix))]
+fn cmd_unlock(_backend: Backend) -> Result<()> {
+    Err(HemliError::Agent("only supported on Unix".into()).into())
+}
+
+/// What `hemli inspect` prints: the stored secret with its TTL also given as a
+/// readable duration.
+#[derive(Serialize)]
+struct Inspected<'a> {
+    #[serde(flatten)]
+    stored: &'a StoredSecret,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<String>,
+}
+
+fn cmd_inspect(store: &dyn SecretStore, namespace: &str, secret: &str) -> Result<()> {
+    let entry = store.get(namespace, secret)?;
     match entry {
         Some(stored) => {
-            let json = serde_json::to_string_pretty(&stored)?;
+            let json = serde_json::to_string_pretty(&Inspected {
+                stored: &stored,
+                ttl: stored.ttl_seconds.map(duration::format_seconds),
+            })?;
             println!("{json}");
             Ok(())
         }
@@ -170,55 +1035,294 @@ fn cmd_inspect(namespace: &str, secret: &str) -> Result<()> {
     }
 }
 
-fn cmd_edit(
-    namespace: &str,
-    secret: &str,
+/// Metadata changes requested by `hemli edit`.
+#[derive(Debug)]
+struct EditOptions {
     ttl: Option<i64>,
     clear_ttl: bool,
     source_sh: Option<String>,
-    source_cmd: Option<String>,
+    source_cmd: Option<Vec<String>>,
+    source_timeout_seconds: Option<u64>,
+    clear_source_timeout: bool,
+    retry: Option<RetryPolicy>,
+    clear_retries: bool,
+    stale_if_error: Option<StaleIfError>,
+    clear_stale_if_error: bool,
+    output_mode: Option<OutputMode>,
+    field: Option<String>,
+    clear_field: bool,
+    fields: BTreeMap<String, String>,
+    clear_fields: bool,
+    ttl_from: Option<TtlFrom>,
+    ttl_margin_seconds: Option<u64>,
+    clear_ttl_from: bool,
+    refresh_ahead_seconds: Option<u64>,
+    clear_refresh_ahead: bool,
+    absolute_expiry: Option<Timestamp>,
+    clear_absolute_expiry: bool,
+    daily_expiry: Option<DailyExpiry>,
+    clear_daily_expiry: bool,
+}
+
+impl EditOptions {
+    fn is_empty(&self) -> bool {
+        self.ttl.is_none()
+            && !self.clear_ttl
+            && self.source_sh.is_none()
+            && self.source_cmd.is_none()
+            && self.source_timeout_seconds.is_none()
+            && !self.clear_source_timeout
+            && self.retry.is_none()
+            && !self.clear_retries
+            && self.stale_if_error.is_none()
+            && !self.clear_stale_if_error
+            && self.output_mode.is_none()
+            && self.field.is_none()
+            && !self.clear_field
+            && self.fields.is_empty()
+            && !self.clear_fields
+            && self.ttl_from.is_none()
+            && self.ttl_margin_seconds.is_none()
+            && !self.clear_ttl_from
+            && self.refresh_ahead_seconds.is_none()
+            && !self.clear_refresh_ahead
+            && self.absolute_expiry.is_none()
+            && !self.clear_absolute_expiry
+            && self.daily_expiry.is_none()
+            && !self.clear_daily_expiry
+    }
+}
+
+fn cmd_edit(
+    store: &dyn SecretStore,
+    namespace: &str,
+    secret: &str,
+    edit: EditOptions,
 ) -> Result<()> {
-    if ttl.is_none() && !clear_ttl && source_sh.is_none() && source_cmd.is_none() {
+    if edit.is_empty() {
         return Err(HemliError::NoModifications.into());
     }
 
-    let mut stored = store::get_secret(namespace, secret)?.ok_or_else(|| HemliError::NotFound {
-        namespace: namespace.to_string(),
-        secret: secret.to_string(),
-    })?;
+    let mut stored = store
+        .get(namespace, secret)?
+        .ok_or_else(|| HemliError::NotFound {
+            namespace: namespace.to_string(),
+            secret: secret.to_string(),
+        })?;
 
-    if clear_ttl {
+    if edit.clear_ttl {
         stored.ttl_seconds = None;
         stored.recalculate_expires_at();
-    } else if let Some(ttl) = ttl {
+    } else if let Some(ttl) = edit.ttl {
         stored.ttl_seconds = Some(ttl);
         stored.recalculate_expires_at();
     }
 
-    if let Some(sh) = source_sh {
-        stored.source_command = Some(sh);
+    if let Some(sh) = edit.source_sh {
+        stored.source_command = Some(SourceCommand::Line(sh));
         stored.source_type = Some(SourceType::Sh);
-    } else if let Some(cmd) = source_cmd {
-        stored.source_command = Some(cmd);
+    } else if let Some(argv) = edit.source_cmd {
+        stored.source_command = Some(SourceCommand::Argv(argv));
         stored.source_type = Some(SourceType::Cmd);
     }
 
-    store::set_secret(namespace, secret, &stored)?;
+    if edit.clear_source_timeout {
+        stored.source_timeout_seconds = None;
+    } else if let Some(timeout) = edit.source_timeout_seconds {
+        stored.source_timeout_seconds = Some(timeout);
+    }
+
+    if edit.clear_retries {
+        stored.retry = None;
+    } else if let Some(retry) = edit.retry {
+        stored.retry = Some(retry);
+    }
+
+    if edit.clear_stale_if_error {
+        stored.stale_if_error = None;
+    } else if let Some(policy) = edit.stale_if_error {
+        stored.stale_if_error = Some(policy);
+    }
+
+    if let Some(mode) = edit.output_mode {
+        stored.output_mode = Some(mode);
+    }
+
+    if edit.clear_field {
+        stored.field = None;
+    } else if let Some(field) = edit.field {
+        stored.field = Some(field);
+    }
+
+    // Field values are filled in by the next fetch
+    if edit.clear_fields {
+        stored.fields.clear();
+        stored.field_values.clear();
+    } else if !edit.fields.is_empty() {
+        stored.fields = edit.fields;
+    }
+
+    // A new source is read from the next fetch on; the margin applies at once
+    if edit.clear_ttl_from {
+        stored.ttl_from = None;
+        stored.ttl_margin_seconds = None;
+        stored.source_expires_at = None;
+        stored.recalculate_expires_at();
+    } else {
+        if let Some(ttl_from) = edit.ttl_from {
+            stored.ttl_from = Some(ttl_from);
+        }
+        if let Some(margin) = edit.ttl_margin_seconds {
+            stored.ttl_margin_seconds = Some(margin);
+            stored.recalculate_expires_at();
+        }
+    }
+
+    if edit.clear_refresh_ahead {
+        stored.refresh_ahead_seconds = None;
+    } else if let Some(window) = edit.refresh_ahead_seconds {
+        stored.refresh_ahead_seconds = Some(window);
+    }
+
+    if edit.clear_absolute_expiry {
+        stored.absolute_expiry = None;
+        stored.recalculate_expires_at();
+    } else if let Some(at) = edit.absolute_expiry {
+        stored.absolute_expiry = Some(at);
+        stored.recalculate_expires_at();
+    }
+
+    if edit.clear_daily_expiry {
+        stored.daily_expiry = None;
+        stored.recalculate_expires_at();
+    } else if let Some(daily) = edit.daily_expiry {
+        stored.daily_expiry = Some(daily);
+        stored.recalculate_expires_at();
+    }
+
+    store.set(namespace, secret, &stored)?;
+    index::record_secret(namespace, secret, &stored)?;
     eprintln!("Updated secret '{secret}' in namespace '{namespace}'");
     Ok(())
 }
 
-fn cmd_list(namespace: Option<&str>) -> Result<()> {
+fn cmd_list(namespace: Option<&str>, format: &ListFormat, filter: ExpiryFilter) -> Result<()> {
     let idx_path = index::index_path();
     let idx = index::load_index(&idx_path)?;
-    let entries = index::filter_entries(&idx, namespace);
+    let now = Timestamp::now();
+    let mut entries = index::filter_entries(&idx, namespace);
+    entries.retain(|e| filter.matches(e, now));
+
+    print!("{}", listing::render(&entries, format, now)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryStore(Mutex<HashMap<(String, String), StoredSecret>>);
+
+    impl SecretStore for MemoryStore {
+        fn get(&self, namespace: &str, name: &str) -> Result<Option<StoredSecret>, HemliError> {
+            let secrets = self.0.lock().unwrap();
+            Ok(secrets.get(&(namespace.into(), name.into())).cloned())
+        }
+
+        fn set(
+            &self,
+            namespace: &str,
+            name: &str,
+            secret: &StoredSecret,
+        ) -> Result<(), HemliError> {
+            let mut secrets = self.0.lock().unwrap();
+            secrets.insert((namespace.into(), name.into()), secret.clone());
+            Ok(())
+        }
 
-    for entry in entries {
-        println!(
-            "{}\t{}\t{}",
-            entry.namespace, entry.secret, entry.created_at
+        fn delete(&self, namespace: &str, name: &str) -> Result<(), HemliError> {
+            self.0
+                .lock()
+                .unwrap()
+                .remove(&(namespace.into(), name.into()));
+            Ok(())
+        }
+    }
+
+    fn expired(value: &str) -> StoredSecret {
+        let mut stored = StoredSecret::new(value.into(), None, None, Some(60));
+        stored.expires_at = Some(Timestamp::now() - SignedDuration::from_secs(1));
+        stored
+    }
+
+    #[test]
+    fn hash_names_a_field_unless_a_secret_has_the_whole_name() {
+        let store = MemoryStore::default();
+        let mut db = StoredSecret::new("{}".into(), None, None, None);
+        db.field_values.insert("user".into(), "app".into());
+        store.set("ns", "db", &db).unwrap();
+        let legacy = StoredSecret::new("old".into(), None, None, None);
+        store.set("ns", "legacy#name", &legacy).unwrap();
+        let resolve = |name| {
+            let opts = ResolveOptions {
+                no_refresh: true,
+                ..Default::default()
+            };
+            resolve_secret(&store, "ns", name, opts)
+        };
+
+        assert_eq!(resolve("db#user").unwrap(), b"app");
+        assert_eq!(resolve("legacy#name").unwrap(), b"old");
+        let err = resolve("new#name").unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(HemliError::InvalidName(name)) if name == "new#name"),
+            "{err}"
         );
     }
 
-    Ok(())
+    #[test]
+    fn lock_fetch_returns_value_stored_before_the_lock_was_free() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("fetch.lock");
+        let store = MemoryStore::default();
+        store.set("ns", "sec", &expired("old")).unwrap();
+
+        // The caller has read the expired value. Another process now takes
+        // the lock, stores a fresh value and releases the lock, all before
+        // the caller tries to take it.
+        let existing = store.get("ns", "sec").unwrap().unwrap();
+        assert!(existing.is_expired());
+        {
+            let _holder = FileLock::acquire(&lock_path).unwrap();
+            let fresh = StoredSecret::new("new".into(), None, None, Some(60));
+            store.set("ns", "sec", &fresh).unwrap();
+        }
+
+        match lock_fetch(&store, "ns", "sec", &lock_path, false).unwrap() {
+            FetchLock::Fetched(entry) => assert_eq!(entry.value, "new"),
+            FetchLock::Acquired(_) => panic!("fetched again despite a fresh value"),
+        }
+    }
+
+    #[test]
+    fn lock_fetch_acquires_for_expired_or_forced() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("fetch.lock");
+        let store = MemoryStore::default();
+
+        store.set("ns", "sec", &expired("old")).unwrap();
+        let lock = lock_fetch(&store, "ns", "sec", &lock_path, false).unwrap();
+        assert!(matches!(lock, FetchLock::Acquired(_)));
+        drop(lock);
+
+        let fresh = StoredSecret::new("new".into(), None, None, Some(60));
+        store.set("ns", "sec", &fresh).unwrap();
+        let lock = lock_fetch(&store, "ns", "sec", &lock_path, true).unwrap();
+        assert!(matches!(lock, FetchLock::Acquired(_)));
+    }
 }
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde::Deserializer;
+
+use crate::duration;
+use crate::error::HemliError;
+use crate::model::SourceCommand;
+use crate::model::SourceType;
+
+pub const MANIFEST_FILE: &str = "hemli.toml";
+
+/// A project manifest declaring the secrets a project needs and where to
+/// fetch them from.
+///
+/// ```toml
+/// namespace = "myapp"
+///
+/// [secrets.db_password]
+/// source_sh = "vault kv get -field=password secret/myapp/db"
+/// ttl = "1h"
+///
+/// [secrets.api_key]
+/// source_cmd = ["op", "read", "op://Dev Vault/api/key"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub secrets: BTreeMap<String, ManifestSecret>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestSecret {
+    pub source_sh: Option<String>,
+    /// An argument list, or a line that is split on whitespace
+    pub source_cmd: Option<SourceCommand>,
+    /// TTL in seconds, given as a number of seconds or a duration like "12h"
+    #[serde(default, deserialize_with = "deserialize_ttl")]
+    pub ttl: Option<i64>,
+}
+
+fn deserialize_ttl<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Ttl {
+        Seconds(i64),
+        Text(String),
+    }
+    let text = match Option::<Ttl>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Ttl::Seconds(secs)) => secs.to_string(),
+        Some(Ttl::Text(text)) => text,
+    };
+    duration::parse(&text)
+        .map(|ttl| Some(duration::ttl_seconds(ttl)))
+        .map_err(serde::de::Error::custom)
+}
+
+impl Manifest {
+    /// The declaration for `secret`, if the manifest applies to `namespace`.
+    ///
+    /// A manifest that declares a namespace only applies to that namespace;
+    /// one without applies to all of them.
+    pub fn secret(&self, namespace: &str, secret: &str) -> Option<&ManifestSecret> {
+        if self.namespace.as_deref().is_some_and(|ns| ns != namespace) {
+            return None;
+        }
+        self.secrets.get(secret)
+    }
+}
+
+impl ManifestSecret {
+    pub fn source(&self) -> Option<(SourceCommand, SourceType)> {
+        match (&self.source_sh, &self.source_cmd) {
+            (Some(sh), _) => Some((sh.as_str().into(), SourceType::Sh)),
+            (None, Some(cmd)) => Some((cmd.clone(), SourceType::Cmd)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Find the nearest `hemli.toml` in `start` or any of its ancestors.
+pub fn find(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(MANIFEST_FILE))
+        .find(|path| path.is_file())
+}
+
+pub fn load(path: &Path) -> Result<Manifest, HemliError> {
+    let invalid = |message: String| HemliError::Manifest {
+        path: path.display().to_string(),
+        message,
+    };
+    let contents = fs::read_to_string(path)?;
+    let manifest: Manifest = toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+    for (name, secret) in &manifest.secrets {
+        if secret.source_sh.is_some() && secret.source_cmd.is_some() {
+            return Err(invalid(format!(
+                "secret '{name}' sets both source_sh and source_cmd"
+            )));
+        }
+    }
+    Ok(manifest)
+}
+
+/// Load the nearest manifest above `start`, if there is one.
+pub fn discover(start: &Path) -> Result<Option<Manifest>, HemliError> {
+    find(start).map(|path| load(&path)).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+        namespace = "myapp"
+
+        [secrets.db_password]
+        source_sh = "vault kv get -field=password secret/myapp/db"
+        ttl = 3600
+
+        [secrets.api_key]
+        source_cmd = "op read op://dev/api/key"
+
+        [secrets.vault_key]
+        source_cmd = ["op", "read", "op://Dev Vault/api/key"]
+    "#;
+
+    fn write_manifest(dir: &Path, contents: &str) -> PathBuf {
+        let path = dir.join(MANIFEST_FILE);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn parse_example() {
+        let manifest: Manifest = toml::from_str(EXAMPLE).unwrap();
+        assert_eq!(manifest.namespace.as_deref(), Some("myapp"));
+        let db = manifest.secret("myapp", "db_password").unwrap();
+        assert_eq!(
+            db.source(),
+            Some((
+                "vault kv get -field=password secret/myapp/db".into(),
+                SourceType::Sh
+            ))
+        );
+        assert_eq!(db.ttl, Some(3600));
+        let api = manifest.secret("myapp", "api_key").unwrap();
+        assert_eq!(api.source().unwrap().1, SourceType::Cmd);
+        assert!(api.ttl.is_none());
+        let vault = manifest.secret("myapp", "vault_key").unwrap();
+        assert_eq!(
+            vault.source().unwrap().0.argv(),
+            ["op", "read", "op://Dev Vault/api/key"]
+        );
+    }
+
+    #[test]
+    fn ttl_accepts_durations() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [secrets.a]
+            ttl = "12h"
+            [secrets.b]
+            ttl = "P7D"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest.secret("ns", "a").unwrap().ttl, Some(12 * 3600));
+        assert_eq!(manifest.secret("ns", "b").unwrap().ttl, Some(7 * 86400));
+
+        for bad in ["ttl = 0", "ttl = -5", "ttl = \"soon\""] {
+            let result = toml::from_str::<Manifest>(&format!("[secrets.x]\n{bad}\n"));
+            assert!(result.is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn secret_ignored_for_other_namespace() {
+        let manifest: Manifest = toml::from_str(EXAMPLE).unwrap();
+        assert!(manifest.secret("other", "db_password").is_none());
+    }
+
+    #[test]
+    fn manifest_without_namespace_applies_everywhere() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [secrets.token]
+            source_sh = "echo hi"
+            "#,
+        )
+        .unwrap();
+        assert!(manifest.secret("anything", "token").is_some());
+    }
+
+    #[test]
+    fn unknown_keys_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_manifest(dir.path(), "namespace = \"x\"\nnamepsace = \"typo\"\n");
+        assert!(matches!(load(&path), Err(HemliError::Manifest { .. })));
+    }
+
+    #[test]
+    fn both_sources_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_manifest(
+            dir.path(),
+            "[secrets.x]\nsource_sh = \"echo a\"\nsource_cmd = \"echo b\"\n",
+        );
+        assert!(matches!(load(&path), Err(HemliError::Manifest { .. })));
+    }
+
+    #[test]
+    fn discover_walks_up() {
+        let dir = tempfile::tempdir().unwrap();
+        write_manifest(dir.path(), EXAMPLE);
+        let nested = dir.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+
+        let manifest = discover(&nested).unwrap().unwrap();
+        assert_eq!(manifest.namespace.as_deref(), Some("myapp"));
+    }
+
+    #[test]
+    fn discover_prefers_nearest() {
+        let dir = tempfile::tempdir().unwrap();
+        write_manifest(dir.path(), "namespace = \"outer\"\n");
+        let nested = dir.path().join("inner");
+        fs::create_dir_all(&nested).unwrap();
+        write_manifest(&nested, "namespace = \"inner\"\n");
+
+        let manifest = discover(&nested).unwrap().unwrap();
+        assert_eq!(manifest.namespace.as_deref(), Some("inner"));
+    }
+}
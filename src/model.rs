@@ -1,7 +1,21 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::ValueEnum;
 use jiff::SignedDuration;
 use jiff::Timestamp;
+use jiff::civil;
+use jiff::tz::TimeZone;
 use serde::Deserialize;
 use serde::Serialize;
+use zeroize::Zeroize;
+
+use crate::error::HemliError;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -10,41 +24,306 @@ pub enum SourceType {
     Cmd,
 }
 
+/// How the output of a source command becomes the secret value.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Text with leading and trailing whitespace removed
+    #[default]
+    Trim,
+    /// The exact bytes, including trailing newlines and binary data
+    Raw,
+    /// The exact bytes, base64-encoded
+    Base64,
+}
+
+/// How [`StoredSecret::value`] is encoded.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueEncoding {
+    /// Base64 of a value that is not valid UTF-8
+    Base64,
+}
+
+/// The command a secret is fetched with.
+///
+/// `sh` sources are a single command line. `cmd` sources are an argument
+/// vector; older versions of hemli stored them as a line, which is split on
+/// whitespace.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SourceCommand {
+    Line(String),
+    Argv(Vec<String>),
+}
+
+impl SourceCommand {
+    /// The command as a single line, for `sh -c`.
+    pub fn line(&self) -> Cow<'_, str> {
+        match self {
+            SourceCommand::Line(line) => Cow::Borrowed(line),
+            SourceCommand::Argv(argv) => Cow::Owned(argv.join(" ")),
+        }
+    }
+
+    /// The program and its arguments, for running without a shell.
+    pub fn argv(&self) -> Vec<&str> {
+        match self {
+            SourceCommand::Line(line) => line.split_whitespace().collect(),
+            SourceCommand::Argv(argv) => argv.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl From<&str> for SourceCommand {
+    fn from(line: &str) -> Self {
+        SourceCommand::Line(line.to_string())
+    }
+}
+
+/// How often to retry a failing source command, and how long to wait between
+/// attempts.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub retries: u32,
+    /// Delay before the first retry; each later retry waits twice as long
+    pub backoff_ms: u64,
+}
+
+impl RetryPolicy {
+    pub const DEFAULT_BACKOFF_MS: u64 = 1000;
+    const MAX_DELAY: Duration = Duration::from_secs(60);
+
+    /// Delay before retry number `retry`, counting from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u64.saturating_pow(retry.saturating_sub(1));
+        Duration::from_millis(self.backoff_ms.saturating_mul(factor)).min(Self::MAX_DELAY)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff_ms: Self::DEFAULT_BACKOFF_MS,
+        }
+    }
+}
+
+/// Where to read a secret's expiry from, instead of guessing a TTL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TtlFrom {
+    /// The `exp` claim of a JWT value
+    Jwt,
+    /// A field of the JSON source output: a number of seconds from now, or a
+    /// timestamp
+    Json(String),
+}
+
+impl FromStr for TtlFrom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "jwt" => Ok(TtlFrom::Jwt),
+            Some(("json", path)) if !path.is_empty() => Ok(TtlFrom::Json(path.to_string())),
+            _ => Err(format!("expected 'jwt' or 'json:<path>', got '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for TtlFrom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TtlFrom::Jwt => write!(f, "jwt"),
+            TtlFrom::Json(path) => write!(f, "json:{path}"),
+        }
+    }
+}
+
+/// A time of day at which a secret expires, every day. Without a time zone
+/// the system time zone applies.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DailyExpiry {
+    pub time: civil::Time,
+    /// IANA time zone name, such as `UTC` or `Europe/Oslo`
+    pub time_zone: Option<String>,
+}
+
+impl DailyExpiry {
+    /// The first occurrence of the time of day strictly after `after`.
+    pub fn next_after(&self, after: Timestamp) -> Option<Timestamp> {
+        let tz = match &self.time_zone {
+            Some(name) => TimeZone::get(name).ok()?,
+            None => TimeZone::system(),
+        };
+        let date = after.to_zoned(tz.clone()).date();
+        let today = date.to_datetime(self.time).to_zoned(tz.clone()).ok()?;
+        if today.timestamp() > after {
+            return Some(today.timestamp());
+        }
+        let tomorrow = date.tomorrow().ok()?.to_datetime(self.time);
+        Some(tomorrow.to_zoned(tz).ok()?.timestamp())
+    }
+}
+
+impl FromStr for DailyExpiry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (time, time_zone) = match s.split_once('[') {
+            Some((time, rest)) => {
+                let tz = rest
+                    .strip_suffix(']')
+                    .ok_or_else(|| format!("expected HH:MM or HH:MM[TZ], got '{s}'"))?;
+                TimeZone::get(tz).map_err(|e| format!("unknown time zone '{tz}': {e}"))?;
+                (time, Some(tz.to_string()))
+            }
+            None => (s, None),
+        };
+        let time = time
+            .parse::<civil::Time>()
+            .map_err(|e| format!("invalid time of day '{time}': {e}"))?;
+        Ok(Self { time, time_zone })
+    }
+}
+
+impl fmt::Display for DailyExpiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.time.second() == 0 && self.time.subsec_nanosecond() == 0 {
+            write!(f, "{}", self.time.strftime("%H:%M"))?;
+        } else {
+            write!(f, "{}", self.time)?;
+        }
+        match &self.time_zone {
+            Some(tz) => write!(f, "[{tz}]"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TryFrom<String> for DailyExpiry {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<DailyExpiry> for String {
+    fn from(daily: DailyExpiry) -> Self {
+        daily.to_string()
+    }
+}
+
+/// Serve the cached value when fetching a fresh one fails.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StaleIfError {
+    /// How long after expiry the cached value may still be served; no limit
+    /// if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_seconds: Option<u64>,
+}
+
+impl StaleIfError {
+    /// Whether a cached value expiring at `expires_at` may be served at `now`.
+    pub fn allows(&self, expires_at: Option<Timestamp>, now: Timestamp) -> bool {
+        match (expires_at, self.max_age_seconds) {
+            (Some(exp), Some(max_age)) => {
+                let max_age = SignedDuration::from_secs(i64::try_from(max_age).unwrap_or(i64::MAX));
+                now.duration_since(exp) <= max_age
+            }
+            _ => true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredSecret {
     pub value: String,
     pub created_at: Timestamp,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub source_command: Option<String>,
+    pub source_command: Option<SourceCommand>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub source_type: Option<SourceType>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ttl_seconds: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expires_at: Option<Timestamp>,
+    /// Fixed point in time after which the secret has expired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub absolute_expiry: Option<Timestamp>,
+    /// Time of day at which the secret expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_expiry: Option<DailyExpiry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_timeout_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_if_error: Option<StaleIfError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_mode: Option<OutputMode>,
+    /// JSON pointer or dotted path selecting the value from JSON output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Named fields extracted from the same JSON output, as name to path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Values of [`StoredSecret::fields`] from the last fetch
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub field_values: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_encoding: Option<ValueEncoding>,
+    /// Read the expiry from the fetched secret itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_from: Option<TtlFrom>,
+    /// How long before the source-reported expiry to treat the secret as
+    /// expired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_margin_seconds: Option<u64>,
+    /// Expiry reported by the source at the last fetch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_expires_at: Option<Timestamp>,
+    /// How long before expiry to start refreshing in the background
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_ahead_seconds: Option<u64>,
 }
 
 impl StoredSecret {
     pub fn new(
         value: String,
-        source_command: Option<String>,
+        source_command: Option<SourceCommand>,
         source_type: Option<SourceType>,
         ttl_seconds: Option<i64>,
     ) -> Self {
-        let created_at = Timestamp::now();
-        let expires_at = ttl_seconds.map(|ttl| {
-            created_at
-                .checked_add(SignedDuration::from_secs(ttl))
-                .unwrap()
-        });
-        Self {
+        let mut secret = Self {
             value,
-            created_at,
+            created_at: Timestamp::now(),
             source_command,
             source_type,
             ttl_seconds,
-            expires_at,
-        }
+            expires_at: None,
+            absolute_expiry: None,
+            daily_expiry: None,
+            source_timeout_seconds: None,
+            retry: None,
+            stale_if_error: None,
+            output_mode: None,
+            field: None,
+            fields: BTreeMap::new(),
+            field_values: BTreeMap::new(),
+            value_encoding: None,
+            ttl_from: None,
+            ttl_margin_seconds: None,
+            source_expires_at: None,
+            refresh_ahead_seconds: None,
+        };
+        secret.recalculate_expires_at();
+        secret
     }
 
     pub fn is_expired(&self) -> bool {
@@ -54,12 +333,114 @@ impl StoredSecret {
         }
     }
 
+    /// Whether the secret has expired or is within its refresh-ahead window
+    /// at `now`.
+    pub fn is_due_for_refresh(&self, now: Timestamp) -> bool {
+        let Some(exp) = self.expires_at else {
+            return false;
+        };
+        let ahead = self.refresh_ahead_seconds.unwrap_or(0);
+        let ahead = SignedDuration::from_secs(i64::try_from(ahead).unwrap_or(i64::MAX));
+        exp.duration_since(now) < ahead
+    }
+
+    /// The value as bytes, decoding it if it is not stored as plain text.
+    pub fn value_bytes(&self) -> Result<Vec<u8>, HemliError> {
+        match self.value_encoding {
+            None => Ok(self.value.clone().into_bytes()),
+            Some(ValueEncoding::Base64) => BASE64
+                .decode(&self.value)
+                .map_err(|e| HemliError::InvalidValue(e.to_string())),
+        }
+    }
+
+    /// Replace the value. Text is stored as is; anything that is not valid
+    /// UTF-8 is stored base64-encoded.
+    pub fn set_value_bytes(&mut self, bytes: Vec<u8>) {
+        match String::from_utf8(bytes) {
+            Ok(text) => {
+                self.value = text;
+                self.value_encoding = None;
+            }
+            Err(e) => {
+                self.value = BASE64.encode(e.as_bytes());
+                self.value_encoding = Some(ValueEncoding::Base64);
+            }
+        }
+    }
+
+    /// Overwrite the value and field values in memory before they are
+    /// dropped.
+    pub fn wipe(&mut self) {
+        self.value.zeroize();
+        for value in self.field_values.values_mut() {
+            value.zeroize();
+        }
+    }
+
+    /// The value of `field`, or the whole value if `field` is `None`.
+    pub fn select(&self, field: Option<&str>) -> Result<Vec<u8>, HemliError> {
+        let Some(name) = field else {
+            return self.value_bytes();
+        };
+        self.field_values
+            .get(name)
+            .map(|value| value.clone().into_bytes())
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.field_values.keys().map(String::as_str).collect();
+                HemliError::UnknownField {
+                    field: name.to_string(),
+                    available: if names.is_empty() {
+                        "none".to_string()
+                    } else {
+                        names.join(", ")
+                    },
+                }
+            })
+    }
+
+    /// A copy of this secret holding a freshly fetched value, with the same
+    /// source and TTL and a new creation time. Any source-reported expiry
+    /// belonged to the old value and is dropped, as is an absolute expiry
+    /// that has passed, so the new value does not expire at once.
+    pub fn refreshed(&self, value: Vec<u8>) -> Self {
+        let mut secret = self.clone();
+        secret.set_value_bytes(value);
+        secret.created_at = Timestamp::now();
+        secret.source_expires_at = None;
+        if secret
+            .absolute_expiry
+            .is_some_and(|exp| exp <= secret.created_at)
+        {
+            secret.absolute_expiry = None;
+        }
+        secret.recalculate_expires_at();
+        secret
+    }
+
+    /// The earliest of the expiry from the TTL, the source-reported expiry
+    /// less the margin, the absolute expiry, and the next daily expiry after
+    /// the secret was fetched.
     pub fn recalculate_expires_at(&mut self) {
-        self.expires_at = self.ttl_seconds.map(|ttl| {
+        // TTLs too long to represent never run out
+        let from_ttl = self.ttl_seconds.map(|ttl| {
             self.created_at
                 .checked_add(SignedDuration::from_secs(ttl))
-                .unwrap()
+                .unwrap_or(Timestamp::MAX)
         });
+        let margin = self.ttl_margin_seconds.unwrap_or(0);
+        let margin = SignedDuration::from_secs(i64::try_from(margin).unwrap_or(i64::MAX));
+        let from_source = self
+            .source_expires_at
+            .map(|exp| exp.checked_sub(margin).unwrap_or(Timestamp::MIN));
+        let daily = self
+            .daily_expiry
+            .as_ref()
+            .and_then(|daily| daily.next_after(self.created_at));
+        self.expires_at = [from_ttl, from_source, self.absolute_expiry, daily]
+            .into_iter()
+            .flatten()
+            .min();
     }
 }
 
@@ -78,7 +459,7 @@ mod tests {
         let json = serde_json::to_string(&secret).unwrap();
         let deserialized: StoredSecret = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.value, "my-secret");
-        assert_eq!(deserialized.source_command.as_deref(), Some("echo hi"));
+        assert_eq!(deserialized.source_command, Some("echo hi".into()));
         assert_eq!(deserialized.source_type, Some(SourceType::Sh));
         assert_eq!(deserialized.ttl_seconds, Some(3600));
         assert!(deserialized.expires_at.is_some());
@@ -118,6 +499,14 @@ mod tests {
         assert!(!json.contains("source_type"));
         assert!(!json.contains("ttl_seconds"));
         assert!(!json.contains("expires_at"));
+        assert!(!json.contains("absolute_expiry"));
+        assert!(!json.contains("daily_expiry"));
+        assert!(!json.contains("source_timeout_seconds"));
+        assert!(!json.contains("retry"));
+        assert!(!json.contains("stale_if_error"));
+        assert!(!json.contains("output_mode"));
+        assert!(!json.contains("field"));
+        assert!(!json.contains("value_encoding"));
     }
 
     #[test]
@@ -133,12 +522,13 @@ mod tests {
         let secret: StoredSecret = serde_json::from_str(json).unwrap();
         assert_eq!(secret.value, "the-secret");
         assert_eq!(
-            secret.source_command.as_deref(),
-            Some("gcloud secrets versions access latest")
+            secret.source_command,
+            Some("gcloud secrets versions access latest".into())
         );
         assert_eq!(secret.source_type, Some(SourceType::Sh));
         assert_eq!(secret.ttl_seconds, Some(3600));
         assert!(secret.expires_at.is_some());
+        assert_eq!(secret.source_timeout_seconds, None);
     }
 
     #[test]
@@ -163,6 +553,287 @@ mod tests {
         assert!(secret.expires_at.is_none());
     }
 
+    #[test]
+    fn refreshed_keeps_source_and_ttl() {
+        let mut secret = StoredSecret::new(
+            "old".into(),
+            Some("echo hi".into()),
+            Some(SourceType::Sh),
+            Some(60),
+        );
+        secret.created_at = Timestamp::from_second(1000).unwrap();
+        secret.recalculate_expires_at();
+        assert!(secret.is_expired());
+
+        secret.source_timeout_seconds = Some(30);
+
+        let fresh = secret.refreshed("new".into());
+        assert_eq!(fresh.value, "new");
+        assert_eq!(fresh.source_command, Some("echo hi".into()));
+        assert_eq!(fresh.source_type, Some(SourceType::Sh));
+        assert_eq!(fresh.ttl_seconds, Some(60));
+        assert_eq!(fresh.source_timeout_seconds, Some(30));
+        assert!(fresh.created_at > secret.created_at);
+        assert!(!fresh.is_expired());
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_a_cap() {
+        let policy = RetryPolicy {
+            retries: 10,
+            backoff_ms: 500,
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_secs(1));
+        assert_eq!(policy.delay(3), Duration::from_secs(2));
+        assert_eq!(policy.delay(10), Duration::from_secs(60));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn retry_policy_serde() {
+        let mut secret = StoredSecret::new("val".into(), None, None, None);
+        secret.retry = Some(RetryPolicy {
+            retries: 3,
+            backoff_ms: 250,
+        });
+        let json = serde_json::to_string(&secret).unwrap();
+        assert!(json.contains(r#""retry":{"retries":3,"backoff_ms":250}"#));
+        let deserialized: StoredSecret = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.retry, secret.retry);
+    }
+
+    #[test]
+    fn text_value_stored_as_is() {
+        let mut secret = StoredSecret::new(String::new(), None, None, None);
+        secret.set_value_bytes(b"line\n\n".to_vec());
+        assert_eq!(secret.value, "line\n\n");
+        assert_eq!(secret.value_encoding, None);
+        assert_eq!(secret.value_bytes().unwrap(), b"line\n\n");
+    }
+
+    #[test]
+    fn binary_value_roundtrips_through_base64() {
+        let bytes = vec![0xff, 0x00, 0x01, b'\n'];
+        let mut secret = StoredSecret::new(String::new(), None, None, None);
+        secret.set_value_bytes(bytes.clone());
+        assert_eq!(secret.value_encoding, Some(ValueEncoding::Base64));
+
+        let json = serde_json::to_string(&secret).unwrap();
+        assert!(json.contains(r#""value_encoding":"base64""#));
+        let deserialized: StoredSecret = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.value_bytes().unwrap(), bytes);
+
+        // Going back to text clears the encoding
+        secret.set_value_bytes(b"text".to_vec());
+        assert_eq!(secret.value_encoding, None);
+    }
+
+    #[test]
+    fn corrupt_base64_value_errors() {
+        let mut secret = StoredSecret::new("not base64!".into(), None, None, None);
+        secret.value_encoding = Some(ValueEncoding::Base64);
+        assert!(matches!(
+            secret.value_bytes(),
+            Err(HemliError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn select_field() {
+        let mut secret = StoredSecret::new("{}".into(), None, None, None);
+        secret.field_values = BTreeMap::from([("user".to_string(), "app".to_string())]);
+        assert_eq!(secret.select(None).unwrap(), b"{}");
+        assert_eq!(secret.select(Some("user")).unwrap(), b"app");
+        match secret.select(Some("password")).unwrap_err() {
+            HemliError::UnknownField { field, available } => {
+                assert_eq!(field, "password");
+                assert_eq!(available, "user");
+            }
+            other => panic!("expected UnknownField, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_ttl_from() {
+        assert_eq!("jwt".parse::<TtlFrom>().unwrap(), TtlFrom::Jwt);
+        assert_eq!(
+            "json:lease_duration".parse::<TtlFrom>().unwrap(),
+            TtlFrom::Json("lease_duration".into())
+        );
+        assert_eq!(
+            "json:/Credentials/Expiration".parse::<TtlFrom>().unwrap(),
+            TtlFrom::Json("/Credentials/Expiration".into())
+        );
+        assert!("json:".parse::<TtlFrom>().is_err());
+        assert!("jwt:x".parse::<TtlFrom>().is_err());
+        assert!("exp".parse::<TtlFrom>().is_err());
+    }
+
+    #[test]
+    fn source_expiry_caps_ttl() {
+        let mut secret = StoredSecret::new("val".into(), None, None, Some(3600));
+        secret.ttl_margin_seconds = Some(60);
+        let at = |secs| {
+            secret
+                .created_at
+                .checked_add(SignedDuration::from_secs(secs))
+                .unwrap()
+        };
+        let (soon, late) = (at(600), at(7200));
+        let (soon_less_margin, ttl) = (at(540), at(3600));
+
+        secret.source_expires_at = Some(soon);
+        secret.recalculate_expires_at();
+        assert_eq!(secret.expires_at, Some(soon_less_margin));
+
+        // A later source expiry does not extend the TTL
+        secret.source_expires_at = Some(late);
+        secret.recalculate_expires_at();
+        assert_eq!(secret.expires_at, Some(ttl));
+
+        // Without a TTL the source expiry alone applies
+        secret.ttl_seconds = None;
+        secret.ttl_margin_seconds = None;
+        secret.recalculate_expires_at();
+        assert_eq!(secret.expires_at, Some(late));
+    }
+
+    #[test]
+    fn refresh_ahead_window() {
+        let mut secret = StoredSecret::new("val".into(), None, None, Some(3600));
+        let at = |secs| {
+            secret
+                .created_at
+                .checked_add(SignedDuration::from_secs(secs))
+                .unwrap()
+        };
+        let (early, late, expired) = (at(600), at(3300), at(3700));
+        assert!(!secret.is_due_for_refresh(late));
+        assert!(secret.is_due_for_refresh(expired));
+
+        secret.refresh_ahead_seconds = Some(600);
+        assert!(!secret.is_due_for_refresh(early));
+        assert!(secret.is_due_for_refresh(late));
+        assert!(secret.is_due_for_refresh(expired));
+
+        // Secrets that never expire are never due
+        secret.ttl_seconds = None;
+        secret.recalculate_expires_at();
+        assert!(!secret.is_due_for_refresh(late));
+    }
+
+    #[test]
+    fn parse_daily_expiry() {
+        let daily: DailyExpiry = "03:00[UTC]".parse().unwrap();
+        assert_eq!(daily.time, civil::time(3, 0, 0, 0));
+        assert_eq!(daily.time_zone.as_deref(), Some("UTC"));
+        assert_eq!(daily.to_string(), "03:00[UTC]");
+
+        let local: DailyExpiry = "17:30".parse().unwrap();
+        assert!(local.time_zone.is_none());
+        assert_eq!(local.to_string(), "17:30");
+
+        for bad in ["25:00", "noon", "03:00[Mars/Olympus]", "03:00[UTC"] {
+            assert!(
+                bad.parse::<DailyExpiry>().is_err(),
+                "{bad} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn daily_expiry_next_occurrence() {
+        let daily: DailyExpiry = "03:00[Europe/Oslo]".parse().unwrap();
+        let before: Timestamp = "2025-01-15T01:00:00Z".parse().unwrap();
+        let after: Timestamp = "2025-01-15T02:00:00Z".parse().unwrap();
+        assert_eq!(
+            daily.next_after(before),
+            Some("2025-01-15T02:00:00Z".parse().unwrap())
+        );
+        // Exactly at the time of day, the next one is tomorrow
+        assert_eq!(
+            daily.next_after(after),
+            Some("2025-01-16T02:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn earliest_expiry_wins() {
+        let mut secret = StoredSecret::new("val".into(), None, None, Some(86400));
+        secret.created_at = "2025-01-15T10:00:00Z".parse().unwrap();
+        secret.daily_expiry = Some("17:00[UTC]".parse().unwrap());
+        secret.recalculate_expires_at();
+        assert_eq!(
+            secret.expires_at,
+            Some("2025-01-15T17:00:00Z".parse().unwrap())
+        );
+
+        secret.absolute_expiry = Some("2025-01-15T12:00:00Z".parse().unwrap());
+        secret.recalculate_expires_at();
+        assert_eq!(secret.expires_at, secret.absolute_expiry);
+
+        // Either form works without a TTL
+        secret.ttl_seconds = None;
+        secret.absolute_expiry = None;
+        secret.recalculate_expires_at();
+        assert_eq!(
+            secret.expires_at,
+            Some("2025-01-15T17:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn refresh_drops_passed_absolute_expiry() {
+        let mut secret = StoredSecret::new("val".into(), None, None, Some(3600));
+        let future: Timestamp = "2099-01-15T12:00:00Z".parse().unwrap();
+        secret.absolute_expiry = Some(future);
+        let refreshed = secret.refreshed(b"new".to_vec());
+        assert_eq!(refreshed.absolute_expiry, Some(future));
+
+        secret.absolute_expiry = Some("2020-01-01T00:00:00Z".parse().unwrap());
+        secret.recalculate_expires_at();
+        assert!(secret.is_expired());
+        let refreshed = secret.refreshed(b"new".to_vec());
+        assert!(refreshed.absolute_expiry.is_none());
+        assert!(!refreshed.is_expired());
+    }
+
+    #[test]
+    fn daily_expiry_serde() {
+        let mut secret = StoredSecret::new("val".into(), None, None, None);
+        secret.daily_expiry = Some("03:00[UTC]".parse().unwrap());
+        let json = serde_json::to_value(&secret).unwrap();
+        assert_eq!(json["daily_expiry"], "03:00[UTC]");
+        let back: StoredSecret = serde_json::from_value(json).unwrap();
+        assert_eq!(back.daily_expiry, secret.daily_expiry);
+    }
+
+    #[test]
+    fn stale_if_error_max_age() {
+        let exp: Timestamp = "2025-01-15T10:00:00Z".parse().unwrap();
+        let soon: Timestamp = "2025-01-15T10:30:00Z".parse().unwrap();
+        let later: Timestamp = "2025-01-15T12:00:00Z".parse().unwrap();
+        let unlimited = StaleIfError::default();
+        let hour = StaleIfError {
+            max_age_seconds: Some(3600),
+        };
+        assert!(unlimited.allows(Some(exp), later));
+        assert!(hour.allows(Some(exp), soon));
+        assert!(!hour.allows(Some(exp), later));
+        assert!(hour.allows(None, later));
+    }
+
+    #[test]
+    fn stale_if_error_serde() {
+        let mut secret = StoredSecret::new("val".into(), None, None, None);
+        secret.stale_if_error = Some(StaleIfError::default());
+        let json = serde_json::to_string(&secret).unwrap();
+        assert!(json.contains(r#""stale_if_error":{}"#));
+        let deserialized: StoredSecret = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.stale_if_error, Some(StaleIfError::default()));
+    }
+
     #[test]
     fn source_type_cmd_serde() {
         let secret = StoredSecret::new(
@@ -176,4 +847,39 @@ mod tests {
         let deserialized: StoredSecret = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.source_type, Some(SourceType::Cmd));
     }
+
+    #[test]
+    fn source_command_argv_serde() {
+        let argv = SourceCommand::Argv(vec!["op".into(), "read".into(), "op://Dev Vault/x".into()]);
+        let secret = StoredSecret::new(
+            "val".into(),
+            Some(argv.clone()),
+            Some(SourceType::Cmd),
+            None,
+        );
+        let json = serde_json::to_string(&secret).unwrap();
+        assert!(json.contains(r#""source_command":["op","read","op://Dev Vault/x"]"#));
+        let deserialized: StoredSecret = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.source_command, Some(argv));
+    }
+
+    #[test]
+    fn legacy_cmd_line_splits_on_whitespace() {
+        let json = r#"{
+            "value": "v",
+            "created_at": "2025-01-15T10:30:00Z",
+            "source_command": "my-cmd  arg1 arg2",
+            "source_type": "cmd"
+        }"#;
+        let secret: StoredSecret = serde_json::from_str(json).unwrap();
+        let command = secret.source_command.unwrap();
+        assert_eq!(command, SourceCommand::Line("my-cmd  arg1 arg2".into()));
+        assert_eq!(command.argv(), ["my-cmd", "arg1", "arg2"]);
+    }
+
+    #[test]
+    fn argv_as_line_joins_with_spaces() {
+        let argv = SourceCommand::Argv(vec!["echo".into(), "a b".into()]);
+        assert_eq!(argv.line(), "echo a b");
+    }
 }
@@ -0,0 +1,175 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use jiff::Timestamp;
+
+use crate::expiry;
+use crate::fsutil;
+use crate::fsutil::FileLock;
+use crate::index;
+use crate::model::StoredSecret;
+use crate::source;
+use crate::source::FetchOptions;
+use crate::source::Fetched;
+use crate::store::SecretStore;
+
+/// How to run the source of a secret, from the settings stored with it.
+pub fn fetch_options(secret: &StoredSecret, default_timeout: Option<Duration>) -> FetchOptions {
+    FetchOptions {
+        timeout: secret
+            .source_timeout_seconds
+            .map(Duration::from_secs)
+            .or(default_timeout),
+        retry: secret.retry.unwrap_or_default(),
+        output: secret.output_mode.unwrap_or_default(),
+        field: secret.field.clone(),
+        fields: secret.fields.clone(),
+    }
+}
+
+/// `stored` with a freshly fetched value and field values, expiring when the
+/// source says it does if the secret reads its expiry from the source. If the
+/// expiry cannot be read, the TTL alone applies.
+pub fn apply_fetched(
+    namespace: &str,
+    secret: &str,
+    stored: &StoredSecret,
+    fetched: Fetched,
+) -> StoredSecret {
+    let source_expiry = stored.ttl_from.as_ref().map(|ttl_from| {
+        expiry::derive(ttl_from, &fetched.output, &fetched.value, Timestamp::now())
+    });
+    let mut fresh = stored.refreshed(fetched.value);
+    fresh.field_values = fetched.fields;
+    match source_expiry {
+        Some(Ok(expires_at)) => {
+            fresh.source_expires_at = Some(expires_at);
+            fresh.recalculate_expires_at();
+        }
+        Some(Err(e)) => {
+            let fallback = if fresh.ttl_seconds.is_some() {
+                "using its TTL"
+            } else {
+                "caching without expiry"
+            };
+            eprintln!("warning: {namespace}/{secret}: {e}; {fallback}");
+        }
+        None => {}
+    }
+    fresh
+}
+
+/// What became of one secret in [`refresh_secrets`].
+#[derive(Debug)]
+pub enum RefreshOutcome {
+    Refreshed,
+    /// Not refreshed, for the given reason
+    Skipped(&'static str),
+    Failed(anyhow::Error),
+}
+
+/// Re-fetch those of `targets` that `select` accepts from their stored
+/// sources, `jobs` at a time, storing the new values. Returns the outcome
+/// for every target that was selected or could not be checked, skipped
+/// targets first; a failure only affects its own secret.
+pub fn refresh_secrets(
+    store: &dyn SecretStore,
+    targets: Vec<(String, String)>,
+    select: impl Fn(&StoredSecret) -> bool,
+    jobs: usize,
+    default_source_timeout: Option<Duration>,
+) -> Vec<(String, String, RefreshOutcome)> {
+    let mut outcomes = Vec::new();
+    let mut pending = Vec::new();
+    for (namespace, secret) in targets {
+        // Hold the fetch lock until the new value is stored, so gets wait for
+        // it and concurrent refreshes leave the secret alone
+        let lock_path = fsutil::fetch_lock_path(&namespace, &secret);
+        let checked = FileLock::try_acquire(&lock_path).and_then(|lock| {
+            // A background refresh waiting to start is now underway, or
+            // someone else is fetching the secret
+            fsutil::clear_refresh_pending(&lock_path);
+            let Some(lock) = lock else {
+                return Ok(Err("being fetched by another process"));
+            };
+            let Some(stored) = store.get(&namespace, &secret)? else {
+                return Ok(Err("not cached"));
+            };
+            Ok(Ok((lock, stored)))
+        });
+        let (lock, stored) = match checked {
+            Ok(Ok(checked)) => checked,
+            Ok(Err(reason)) => {
+                outcomes.push((namespace, secret, RefreshOutcome::Skipped(reason)));
+                continue;
+            }
+            Err(e) => {
+                outcomes.push((namespace, secret, RefreshOutcome::Failed(e.into())));
+                continue;
+            }
+        };
+        if !select(&stored) {
+            continue;
+        }
+        let (Some(cmd), Some(source_type)) = (stored.source_command.clone(), stored.source_type)
+        else {
+            outcomes.push((
+                namespace,
+                secret,
+                RefreshOutcome::Skipped("no stored source"),
+            ));
+            continue;
+        };
+        let fetch_opts = fetch_options(&stored, default_source_timeout);
+        pending.push((
+            namespace,
+            secret,
+            stored,
+            cmd,
+            source_type,
+            fetch_opts,
+            lock,
+        ));
+    }
+
+    // Run sources in parallel; each worker claims the next pending secret
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(pending.len()) {
+            let tx = tx.clone();
+            let next = &next;
+            let pending = &pending;
+            scope.spawn(move || {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((_, _, _, cmd, source_type, fetch_opts, _)) = pending.get(i) else {
+                        break;
+                    };
+                    let _ = tx.send((i, source::fetch_secret(cmd, source_type, fetch_opts)));
+                }
+            });
+        }
+    });
+    drop(tx);
+
+    let mut results: Vec<_> = rx.into_iter().collect();
+    results.sort_by_key(|(i, _)| *i);
+
+    for ((namespace, secret, stored, ..), (_, result)) in pending.into_iter().zip(results) {
+        let stored_result = result.map_err(anyhow::Error::from).and_then(|fetched| {
+            let fresh = apply_fetched(&namespace, &secret, &stored, fetched);
+            store.set(&namespace, &secret, &fresh)?;
+            Ok(index::record_secret(&namespace, &secret, &fresh)?)
+        });
+        let outcome = match stored_result {
+            Ok(()) => RefreshOutcome::Refreshed,
+            Err(e) => RefreshOutcome::Failed(e),
+        };
+        outcomes.push((namespace, secret, outcome));
+    }
+    outcomes
+}
@@ -1,20 +1,122 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::process::Child;
 use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use tracing::debug;
 
 use crate::error::HemliError;
+use crate::field;
+use crate::model::OutputMode;
+use crate::model::RetryPolicy;
+use crate::model::SourceCommand;
 use crate::model::SourceType;
 
-pub fn fetch_secret(command: &str, source_type: &SourceType) -> Result<String, HemliError> {
-    let output = match source_type {
-        SourceType::Sh => Command::new("sh").arg("-c").arg(command).output()?,
+/// How to run a source command.
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// Kill the command after this long
+    pub timeout: Option<Duration>,
+    /// Retry failed or timed-out attempts
+    pub retry: RetryPolicy,
+    /// How the output becomes the value
+    pub output: OutputMode,
+    /// Parse the output as JSON and use this field as the value
+    pub field: Option<String>,
+    /// Named fields to extract from JSON output, as name to path
+    pub fields: BTreeMap<String, String>,
+}
+
+/// The result of running a source command.
+#[derive(Debug)]
+pub struct Fetched {
+    /// The value, as set by [`FetchOptions::output`] and
+    /// [`FetchOptions::field`]
+    pub value: Vec<u8>,
+    /// Values of [`FetchOptions::fields`]
+    pub fields: BTreeMap<String, String>,
+    /// The command's output before any conversion
+    pub output: Vec<u8>,
+}
+
+/// Run a source command and turn its output into a value, retrying according
+/// to `opts.retry` if it fails or times out. Errors starting the command at
+/// all are not retried.
+pub fn fetch_secret(
+    command: &SourceCommand,
+    source_type: &SourceType,
+    opts: &FetchOptions,
+) -> Result<Fetched, HemliError> {
+    let output = run_with_retries(command, source_type, opts)?;
+    let fields = field::extract_all(&output, &opts.fields)?;
+    let value = match &opts.field {
+        Some(path) => field::extract(&output, path)?,
+        None => output.clone(),
+    };
+    Ok(Fetched {
+        value: convert(value, opts.output),
+        fields,
+        output,
+    })
+}
+
+fn run_with_retries(
+    command: &SourceCommand,
+    source_type: &SourceType,
+    opts: &FetchOptions,
+) -> Result<Vec<u8>, HemliError> {
+    let attempts = opts.retry.retries.saturating_add(1);
+    let mut attempt = 1;
+    loop {
+        debug!(attempt, attempts, "running source command");
+        match fetch_once(command, source_type, opts.timeout) {
+            Err(e @ (HemliError::SourceFailed(_) | HemliError::SourceTimeout(_)))
+                if attempt < attempts =>
+            {
+                let delay = opts.retry.delay(attempt);
+                debug!(attempt, attempts, error = %e, ?delay, "source command failed, retrying");
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn fetch_once(
+    command: &SourceCommand,
+    source_type: &SourceType,
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>, HemliError> {
+    let mut cmd = match source_type {
+        SourceType::Sh => {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(command.line().as_ref());
+            cmd
+        }
         SourceType::Cmd => {
-            let parts: Vec<&str> = command.split_whitespace().collect();
-            if parts.is_empty() {
+            let argv = command.argv();
+            let Some((program, args)) = argv.split_first() else {
                 return Err(HemliError::SourceFailed("empty command".into()));
-            }
-            Command::new(parts[0]).args(&parts[1..]).output()?
+            };
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            cmd
         }
     };
 
+    let output = match timeout {
+        Some(timeout) => output_with_timeout(cmd, timeout)?,
+        None => cmd.output()?,
+    };
+
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(HemliError::SourceFailed(format!(
@@ -24,29 +126,241 @@ pub fn fetch_secret(command: &str, source_type: &SourceType) -> Result<String, H
         )));
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    Ok(stdout.trim().to_string())
+    Ok(output.stdout)
+}
+
+fn convert(stdout: Vec<u8>, mode: OutputMode) -> Vec<u8> {
+    match mode {
+        OutputMode::Trim => String::from_utf8_lossy(&stdout).trim().as_bytes().to_vec(),
+        OutputMode::Raw => stdout,
+        OutputMode::Base64 => BASE64.encode(stdout).into_bytes(),
+    }
+}
+
+/// Like [`Command::output`], but kills the command if it runs longer than
+/// `timeout`. Stdin is inherited, as without a timeout.
+///
+/// On Unix, unless hemli has a terminal the command could prompt on, it runs
+/// in its own process group so that a timeout kills everything it started.
+/// On a terminal it must stay in the foreground group to read from it, and a
+/// timeout kills only the command itself. Once the command exits, whatever
+/// it left running in the background is left alone, and the output it wrote
+/// is returned without waiting for those processes to close the pipes.
+fn output_with_timeout(mut cmd: Command, timeout: Duration) -> Result<Output, HemliError> {
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let own_group = cfg!(unix) && !has_terminal();
+    #[cfg(unix)]
+    if own_group {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    let mut child = cmd.spawn()?;
+    let mut output = Captured::new(&mut child);
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            // The child is not reaped yet, so its pid still names its
+            // process group
+            kill(&mut child, own_group);
+            child.wait()?;
+            return Err(HemliError::SourceTimeout(timeout));
+        }
+        output.read_for((deadline - now).min(POLL_INTERVAL))?;
+    };
+
+    let (stdout, stderr) = output.finish()?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// How often a command run with a timeout is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Whether a command could prompt the user on a terminal.
+fn has_terminal() -> bool {
+    std::fs::File::open("/dev/tty").is_ok()
+}
+
+/// The stdout and stderr of a command, read as it runs.
+#[cfg(unix)]
+struct Captured {
+    /// The pipes still open, with what was read from them
+    pipes: [(Option<std::fs::File>, Vec<u8>); 2],
+}
+
+#[cfg(unix)]
+impl Captured {
+    fn new(child: &mut Child) -> Self {
+        use std::os::fd::OwnedFd;
+        let stdout = child.stdout.take().map(|p| OwnedFd::from(p).into());
+        let stderr = child.stderr.take().map(|p| OwnedFd::from(p).into());
+        Self {
+            pipes: [(stdout, Vec::new()), (stderr, Vec::new())],
+        }
+    }
+
+    /// Wait up to `wait` for output and read whatever has arrived. Returns
+    /// whether anything was read.
+    fn read_for(&mut self, wait: Duration) -> Result<bool, HemliError> {
+        use std::os::fd::AsRawFd;
+        let mut fds = self.pipes.each_ref().map(|(pipe, _)| libc::pollfd {
+            // poll ignores negative descriptors
+            fd: pipe.as_ref().map_or(-1, |p| p.as_raw_fd()),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        if fds.iter().all(|fd| fd.fd < 0) {
+            thread::sleep(wait);
+            return Ok(false);
+        }
+        let millis = libc::c_int::try_from(wait.as_millis()).unwrap_or(libc::c_int::MAX);
+        // SAFETY: `fds` is valid for reads and writes of its length for the
+        // duration of the call.
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, millis) };
+        if ret == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(err.into());
+        }
+
+        let mut read_any = false;
+        for ((pipe, buf), fd) in self.pipes.iter_mut().zip(fds) {
+            let Some(file) = pipe else { continue };
+            if fd.revents == 0 {
+                continue;
+            }
+            // Ready, so this does not block
+            let mut chunk = [0; 8192];
+            match file.read(&mut chunk) {
+                Ok(0) => *pipe = None,
+                Ok(n) => {
+                    buf.extend_from_slice(&chunk[..n]);
+                    read_any = true;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(read_any)
+    }
+
+    /// The output of a command that has exited: what it wrote is already
+    /// in the pipes, so stop at whatever is there rather than at the end of
+    /// the pipes, which background processes may hold open.
+    fn finish(mut self) -> Result<(Vec<u8>, Vec<u8>), HemliError> {
+        while self.read_for(Duration::ZERO)? {}
+        let [(_, stdout), (_, stderr)] = self.pipes;
+        Ok((stdout, stderr))
+    }
+}
+
+/// The stdout and stderr of a command, read as it runs.
+#[cfg(not(unix))]
+struct Captured {
+    stdout: thread::JoinHandle<Vec<u8>>,
+    stderr: thread::JoinHandle<Vec<u8>>,
+}
+
+#[cfg(not(unix))]
+impl Captured {
+    fn new(child: &mut Child) -> Self {
+        Self {
+            stdout: read_in_background(child.stdout.take()),
+            stderr: read_in_background(child.stderr.take()),
+        }
+    }
+
+    fn read_for(&mut self, wait: Duration) -> Result<bool, HemliError> {
+        thread::sleep(wait);
+        Ok(false)
+    }
+
+    fn finish(self) -> Result<(Vec<u8>, Vec<u8>), HemliError> {
+        Ok((
+            self.stdout.join().unwrap_or_default(),
+            self.stderr.join().unwrap_or_default(),
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Kill a command that has not been reaped, together with its process group
+/// if it leads one.
+fn kill(child: &mut Child, own_group: bool) {
+    #[cfg(unix)]
+    if own_group {
+        // Never signal group 0 (our own) or -1 (every process we may signal)
+        let Ok(pgid) = libc::pid_t::try_from(child.id()) else {
+            return;
+        };
+        if pgid <= 1 {
+            return;
+        }
+        // SAFETY: kill has no memory-safety preconditions. The child has not
+        // been reaped, so `pgid` cannot have been reused by another group.
+        unsafe {
+            libc::kill(-pgid, libc::SIGKILL);
+        }
+        return;
+    }
+    #[cfg(not(unix))]
+    let _ = own_group;
+    let _ = child.kill();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn cmd(line: &str) -> SourceCommand {
+        line.into()
+    }
+
     #[test]
     fn sh_echo() {
-        let result = fetch_secret("echo hello", &SourceType::Sh).unwrap();
-        assert_eq!(result, "hello");
+        let result = fetch_secret(
+            &cmd("echo hello"),
+            &SourceType::Sh,
+            &FetchOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.value, b"hello");
     }
 
     #[test]
     fn cmd_echo() {
-        let result = fetch_secret("echo hello", &SourceType::Cmd).unwrap();
-        assert_eq!(result, "hello");
+        let result = fetch_secret(
+            &cmd("echo hello"),
+            &SourceType::Cmd,
+            &FetchOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.value, b"hello");
     }
 
     #[test]
     fn sh_failure() {
-        let result = fetch_secret("exit 1", &SourceType::Sh);
+        let result = fetch_secret(&cmd("exit 1"), &SourceType::Sh, &FetchOptions::default());
         assert!(result.is_err());
         match result.unwrap_err() {
             HemliError::SourceFailed(_) => {}
@@ -56,19 +370,240 @@ mod tests {
 
     #[test]
     fn cmd_failure() {
-        let result = fetch_secret("false", &SourceType::Cmd);
+        let result = fetch_secret(&cmd("false"), &SourceType::Cmd, &FetchOptions::default());
         assert!(result.is_err());
     }
 
     #[test]
     fn whitespace_trimming() {
-        let result = fetch_secret("echo '  hello  '", &SourceType::Sh).unwrap();
-        assert_eq!(result, "hello");
+        let result = fetch_secret(
+            &cmd("echo '  hello  '"),
+            &SourceType::Sh,
+            &FetchOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.value, b"hello");
+    }
+
+    fn with_timeout(millis: u64) -> FetchOptions {
+        FetchOptions {
+            timeout: Some(Duration::from_millis(millis)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn timeout_not_reached() {
+        let result = fetch_secret(&cmd("echo fast"), &SourceType::Sh, &with_timeout(5000)).unwrap();
+        assert_eq!(result.value, b"fast");
+    }
+
+    #[test]
+    fn timeout_kills_command() {
+        let start = Instant::now();
+        let result = fetch_secret(&cmd("sleep 10"), &SourceType::Sh, &with_timeout(200));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        match result.unwrap_err() {
+            HemliError::SourceTimeout(t) => assert_eq!(t, Duration::from_millis(200)),
+            other => panic!("expected SourceTimeout, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn timeout_kills_grandchildren_holding_stdout() {
+        // The backgrounded sleep inherits stdout; if it survived, reading
+        // the output would block until it exits
+        let start = Instant::now();
+        let result = fetch_secret(
+            &cmd("sleep 10 & sleep 10"),
+            &SourceType::Sh,
+            &with_timeout(200),
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(matches!(result, Err(HemliError::SourceTimeout(_))));
+    }
+
+    #[test]
+    fn exited_command_does_not_wait_for_its_background_processes() {
+        // The backgrounded sleep inherits stdout, which would block reading
+        // the output until the timeout or the sleep ends
+        let start = Instant::now();
+        let result = fetch_secret(
+            &cmd("sleep 10 & echo done"),
+            &SourceType::Sh,
+            &with_timeout(5000),
+        )
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert_eq!(result.value, b"done");
+    }
+
+    #[test]
+    fn exited_command_leaves_its_background_processes_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("alive");
+        let line = format!("(sleep 0.2; touch {}) & echo done", marker.display());
+        let result = fetch_secret(&cmd(&line), &SourceType::Sh, &with_timeout(5000)).unwrap();
+        assert_eq!(result.value, b"done");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !marker.exists() {
+            assert!(Instant::now() < deadline, "background process was killed");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn timeout_still_reports_failure() {
+        let result = fetch_secret(
+            &cmd("echo oops >&2; exit 2"),
+            &SourceType::Sh,
+            &with_timeout(5000),
+        );
+        match result.unwrap_err() {
+            HemliError::SourceFailed(msg) => assert!(msg.contains("oops")),
+            other => panic!("expected SourceFailed, got {other:?}"),
+        }
+    }
+
+    fn with_retries(retries: u32) -> FetchOptions {
+        FetchOptions {
+            retry: RetryPolicy {
+                retries,
+                backoff_ms: 10,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn retries_until_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("attempts");
+        // Fails on the first two attempts, then succeeds
+        let script = format!(
+            "echo x >> {0}; test $(wc -l < {0}) -ge 3 && echo third-time",
+            counter.display()
+        );
+        let result = fetch_secret(&cmd(&script), &SourceType::Sh, &with_retries(2)).unwrap();
+        assert_eq!(result.value, b"third-time");
+    }
+
+    #[test]
+    fn retries_exhausted_returns_last_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("attempts");
+        let script = format!("echo x >> {0}; echo nope >&2; exit 1", counter.display());
+        let result = fetch_secret(&cmd(&script), &SourceType::Sh, &with_retries(2));
+        match result.unwrap_err() {
+            HemliError::SourceFailed(msg) => assert!(msg.contains("nope")),
+            other => panic!("expected SourceFailed, got {other:?}"),
+        }
+        let attempts = std::fs::read_to_string(&counter).unwrap();
+        assert_eq!(attempts.lines().count(), 3);
+    }
+
+    #[test]
+    fn spawn_errors_are_not_retried() {
+        let start = Instant::now();
+        let mut opts = with_retries(3);
+        opts.retry.backoff_ms = 5000;
+        let result = fetch_secret(
+            &cmd("nonexistent-command-hemli-test"),
+            &SourceType::Cmd,
+            &opts,
+        );
+        assert!(matches!(result, Err(HemliError::Io(_))));
+        assert!(start.elapsed() < Duration::from_secs(5));
     }
 
     #[test]
     fn sh_multiword_output() {
-        let result = fetch_secret("echo 'hello world'", &SourceType::Sh).unwrap();
-        assert_eq!(result, "hello world");
+        let result = fetch_secret(
+            &cmd("echo 'hello world'"),
+            &SourceType::Sh,
+            &FetchOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(result.value, b"hello world");
+    }
+
+    #[test]
+    fn cmd_argv_keeps_spaces_and_quotes() {
+        let argv = SourceCommand::Argv(vec![
+            "printf".into(),
+            "%s|%s".into(),
+            "Dev Vault".into(),
+            "\"quoted\"".into(),
+        ]);
+        let result = fetch_secret(&argv, &SourceType::Cmd, &FetchOptions::default()).unwrap();
+        assert_eq!(result.value, b"Dev Vault|\"quoted\"");
+    }
+
+    #[test]
+    fn cmd_empty_argv_errors() {
+        let argv = SourceCommand::Argv(vec![]);
+        let result = fetch_secret(&argv, &SourceType::Cmd, &FetchOptions::default());
+        assert!(matches!(result, Err(HemliError::SourceFailed(_))));
+    }
+
+    fn with_output(output: OutputMode) -> FetchOptions {
+        FetchOptions {
+            output,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn raw_output_keeps_trailing_newlines() {
+        let result = fetch_secret(
+            &cmd("printf '  key\\n\\n'"),
+            &SourceType::Sh,
+            &with_output(OutputMode::Raw),
+        )
+        .unwrap();
+        assert_eq!(result.value, b"  key\n\n");
+    }
+
+    #[test]
+    fn raw_output_keeps_binary() {
+        let result = fetch_secret(
+            &cmd("printf '\\377\\000\\001'"),
+            &SourceType::Sh,
+            &with_output(OutputMode::Raw),
+        )
+        .unwrap();
+        assert_eq!(result.value, [0xff, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn base64_output() {
+        let result = fetch_secret(
+            &cmd("printf '\\377\\000\\001'"),
+            &SourceType::Sh,
+            &with_output(OutputMode::Base64),
+        )
+        .unwrap();
+        assert_eq!(result.value, b"/wAB");
+    }
+
+    #[test]
+    fn named_fields_from_one_run() {
+        let opts = FetchOptions {
+            fields: BTreeMap::from([
+                ("user".to_string(), "db.user".to_string()),
+                ("password".to_string(), "/db/password".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let result = fetch_secret(
+            &cmd(r#"echo '{"db": {"user": "app", "password": "hunter2"}}'"#),
+            &SourceType::Sh,
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(result.fields["user"], "app");
+        assert_eq!(result.fields["password"], "hunter2");
+        assert!(result.value.starts_with(b"{"));
     }
 }
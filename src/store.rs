@@ -1,35 +1,129 @@
+#[cfg(unix)]
+mod agent;
+mod file;
+
+use clap::ValueEnum;
+
 use crate::error::HemliError;
 use crate::model::StoredSecret;
+#[cfg(unix)]
+pub use agent::AgentStore;
+pub use file::FileStore;
+pub use file::read_passphrase;
 
-pub fn service_name(namespace: &str) -> String {
-    format!("hemli:{namespace}")
+/// Storage backend selectable via `--backend` / `HEMLI_BACKEND`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// OS-native keyring (Keychain, Secret Service, Credential Manager)
+    #[default]
+    Keyring,
+    /// Passphrase-encrypted file in the local data directory
+    File,
+}
+
+/// A place where hemli persists `StoredSecret` records.
+pub trait SecretStore: Send + Sync {
+    fn get(&self, namespace: &str, name: &str) -> Result<Option<StoredSecret>, HemliError>;
+
+    fn set(&self, namespace: &str, name: &str, secret: &StoredSecret) -> Result<(), HemliError>;
+
+    /// Deleting a non-existent secret is not an error.
+    fn delete(&self, namespace: &str, name: &str) -> Result<(), HemliError>;
+
+    /// Every stored `(namespace, name)` pair, or `None` if the backend cannot
+    /// enumerate its contents.
+    fn list(&self) -> Result<Option<Vec<(String, String)>>, HemliError> {
+        Ok(None)
+    }
+
+    /// Authenticate ahead of use. Stores without a passphrase ignore it.
+    fn unlock(&self, _passphrase: &str) -> Result<(), HemliError> {
+        Ok(())
+    }
+
+    /// Forget any key held in memory, so the next use has to unlock again.
+    fn lock(&self) {}
+
+    /// Ask for whatever the store needs to be used, like a passphrase, now
+    /// rather than on first access.
+    fn authenticate(&self) -> Result<(), HemliError> {
+        Ok(())
+    }
 }
 
-pub fn get_secret(namespace: &str, name: &str) -> Result<Option<StoredSecret>, HemliError> {
-    let entry = keyring::Entry::new(&service_name(namespace), name)?;
-    match entry.get_password() {
-        Ok(json) => {
-            let secret: StoredSecret = serde_json::from_str(&json)?;
-            Ok(Some(secret))
+/// The store for `backend`, going through `hemli agent` if one is running.
+pub fn open(backend: Backend) -> Box<dyn SecretStore> {
+    let direct = open_direct(backend);
+    #[cfg(unix)]
+    {
+        let socket = crate::agent::socket_path(backend);
+        if crate::agent::is_listening(&socket) {
+            return Box::new(AgentStore::new(socket, direct));
         }
-        Err(keyring::Error::NoEntry) => Ok(None),
-        Err(e) => Err(e.into()),
     }
+    direct
 }
 
-pub fn set_secret(namespace: &str, name: &str, secret: &StoredSecret) -> Result<(), HemliError> {
-    let entry = keyring::Entry::new(&service_name(namespace), name)?;
-    let json = serde_json::to_string(secret)?;
-    entry.set_password(&json)?;
-    Ok(())
+/// Whether the store for `backend` can be used without asking for a
+/// passphrase on the terminal: the file backend needs `HEMLI_PASSPHRASE` or a
+/// running agent.
+pub fn opens_unattended(backend: Backend) -> bool {
+    match backend {
+        Backend::Keyring => true,
+        Backend::File => {
+            #[cfg(unix)]
+            if crate::agent::is_listening(&crate::agent::socket_path(backend)) {
+                return true;
+            }
+            std::env::var_os("HEMLI_PASSPHRASE").is_some()
+        }
+    }
+}
+
+/// The store for `backend`, bypassing any running agent.
+pub fn open_direct(backend: Backend) -> Box<dyn SecretStore> {
+    match backend {
+        Backend::Keyring => Box::new(KeyringStore),
+        Backend::File => Box::new(FileStore::new(file::secrets_path())),
+    }
+}
+
+pub fn service_name(namespace: &str) -> String {
+    format!("hemli:{namespace}")
 }
 
-pub fn delete_secret(namespace: &str, name: &str) -> Result<(), HemliError> {
-    let entry = keyring::Entry::new(&service_name(namespace), name)?;
-    match entry.delete_credential() {
-        Ok(()) => Ok(()),
-        Err(keyring::Error::NoEntry) => Ok(()),
-        Err(e) => Err(e.into()),
+/// Stores each secret as a JSON payload in the OS keyring, using
+/// `hemli:<namespace>` as the service and the secret name as the account.
+#[derive(Debug, Default)]
+pub struct KeyringStore;
+
+impl SecretStore for KeyringStore {
+    fn get(&self, namespace: &str, name: &str) -> Result<Option<StoredSecret>, HemliError> {
+        let entry = keyring::Entry::new(&service_name(namespace), name)?;
+        match entry.get_password() {
+            Ok(json) => {
+                let secret: StoredSecret = serde_json::from_str(&json)?;
+                Ok(Some(secret))
+            }
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set(&self, namespace: &str, name: &str, secret: &StoredSecret) -> Result<(), HemliError> {
+        let entry = keyring::Entry::new(&service_name(namespace), name)?;
+        let json = serde_json::to_string(secret)?;
+        entry.set_password(&json)?;
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, name: &str) -> Result<(), HemliError> {
+        let entry = keyring::Entry::new(&service_name(namespace), name)?;
+        match entry.delete_credential() {
+            Ok(()) => Ok(()),
+            Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
     }
 }
 
@@ -46,37 +140,38 @@ mod tests {
     #[test]
     #[ignore] // Requires OS keyring access
     fn get_set_delete_roundtrip() {
+        let store = KeyringStore;
         let ns = "hemli-test-roundtrip";
         let name = "test-secret";
 
         // Clean up first
-        let _ = delete_secret(ns, name);
+        let _ = store.delete(ns, name);
 
         // Get should return None
-        let result = get_secret(ns, name).unwrap();
+        let result = store.get(ns, name).unwrap();
         assert!(result.is_none());
 
         // Set
         let secret = StoredSecret::new("test-value".into(), None, None, None);
-        set_secret(ns, name, &secret).unwrap();
+        store.set(ns, name, &secret).unwrap();
 
         // Get should return the secret
-        let result = get_secret(ns, name).unwrap();
+        let result = store.get(ns, name).unwrap();
         assert!(result.is_some());
         assert_eq!(result.unwrap().value, "test-value");
 
         // Delete
-        delete_secret(ns, name).unwrap();
+        store.delete(ns, name).unwrap();
 
         // Get should return None again
-        let result = get_secret(ns, name).unwrap();
+        let result = store.get(ns, name).unwrap();
         assert!(result.is_none());
     }
 
     #[test]
     #[ignore] // Requires OS keyring access
     fn delete_nonexistent_is_ok() {
-        let result = delete_secret("hemli-test-nonexistent", "nonexistent");
+        let result = KeyringStore.delete("hemli-test-nonexistent", "nonexistent");
         assert!(result.is_ok());
     }
 }
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use super::SecretStore;
+use crate::agent::Request;
+use crate::agent::Response;
+use crate::error::HemliError;
+use crate::model::StoredSecret;
+
+/// Goes through a running `hemli agent`, and straight to the backend whenever
+/// no agent is listening on its socket.
+pub struct AgentStore {
+    socket: PathBuf,
+    direct: Box<dyn SecretStore>,
+}
+
+impl AgentStore {
+    pub fn new(socket: PathBuf, direct: Box<dyn SecretStore>) -> Self {
+        Self { socket, direct }
+    }
+
+    /// The agent's answer, or `None` if it is not running.
+    fn send(&self, request: &Request) -> Result<Option<Response>, HemliError> {
+        crate::agent::request(&self.socket, request)
+    }
+}
+
+fn unexpected(response: Response) -> HemliError {
+    HemliError::Agent(format!("unexpected answer {response:?}"))
+}
+
+impl SecretStore for AgentStore {
+    fn get(&self, namespace: &str, name: &str) -> Result<Option<StoredSecret>, HemliError> {
+        let request = Request::Get {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+        };
+        match self.send(&request)? {
+            None => self.direct.get(namespace, name),
+            Some(Response::Secret { secret }) => Ok(secret.map(|secret| *secret)),
+            Some(other) => Err(unexpected(other)),
+        }
+    }
+
+    fn set(&self, namespace: &str, name: &str, secret: &StoredSecret) -> Result<(), HemliError> {
+        let request = Request::Set {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            secret: Box::new(secret.clone()),
+        };
+        match self.send(&request)? {
+            None => self.direct.set(namespace, name, secret),
+            Some(Response::Done) => Ok(()),
+            Some(other) => Err(unexpected(other)),
+        }
+    }
+
+    fn delete(&self, namespace: &str, name: &str) -> Result<(), HemliError> {
+        let request = Request::Delete {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+        };
+        match self.send(&request)? {
+            None => self.direct.delete(namespace, name),
+            Some(Response::Done) => Ok(()),
+            Some(other) => Err(unexpected(other)),
+        }
+    }
+
+    fn list(&self) -> Result<Option<Vec<(String, String)>>, HemliError> {
+        match self.send(&Request::List)? {
+            None => self.direct.list(),
+            Some(Response::Names { names }) => Ok(names),
+            Some(other) => Err(unexpected(other)),
+        }
+    }
+}
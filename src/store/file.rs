@@ -0,0 +1,492 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use argon2::Algorithm;
+use argon2::Argon2;
+use argon2::Params;
+use argon2::Version;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::AeadCore;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::Key;
+use chacha20poly1305::KeyInit;
+use chacha20poly1305::Nonce;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use serde::Deserialize;
+use serde::Serialize;
+use zeroize::Zeroize;
+use zeroize::Zeroizing;
+
+use super::SecretStore;
+use crate::error::HemliError;
+use crate::fsutil;
+use crate::fsutil::FileLock;
+use crate::model::StoredSecret;
+
+const FORMAT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+
+/// Plaintext contents of the secrets file: namespace -> secret name -> record.
+type Secrets = BTreeMap<String, BTreeMap<String, StoredSecret>>;
+
+pub fn secrets_path() -> PathBuf {
+    let data_dir = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    data_dir.join("hemli").join("secrets.enc")
+}
+
+/// On-disk envelope. Everything but the KDF parameters is opaque.
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    kdf: KdfParams,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct KdfParams {
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl KdfParams {
+    fn generate() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            salt: BASE64.encode(salt),
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<Key, HemliError> {
+        let salt = BASE64
+            .decode(&self.salt)
+            .map_err(|e| HemliError::SecretsFile(format!("invalid salt: {e}")))?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| HemliError::SecretsFile(format!("invalid KDF parameters: {e}")))?;
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| HemliError::SecretsFile(format!("key derivation failed: {e}")))?;
+        Ok(key)
+    }
+}
+
+/// The key derived from the passphrase, wiped from memory when dropped.
+struct Unlocked {
+    kdf: KdfParams,
+    key: Key,
+    /// Kept only while there is no file to check the key against, in case
+    /// another process creates it first under a different salt
+    passphrase: Option<Zeroizing<String>>,
+}
+
+impl Drop for Unlocked {
+    fn drop(&mut self) {
+        self.key.as_mut_slice().zeroize();
+    }
+}
+
+enum KeyState {
+    /// Not used yet; the passphrase is read on first access
+    Pending,
+    Unlocked(Unlocked),
+    /// Locked with `lock`; only an explicit `unlock` opens the store again
+    Locked,
+}
+
+/// Stores all secrets in a single file, encrypted with ChaCha20-Poly1305
+/// under a key derived from a passphrase with Argon2id.
+///
+/// The passphrase is taken from `HEMLI_PASSPHRASE` or prompted for on the
+/// terminal the first time the store is accessed.
+pub struct FileStore {
+    path: PathBuf,
+    key: Mutex<KeyState>,
+}
+
+impl FileStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            key: Mutex::new(KeyState::Pending),
+        }
+    }
+
+    fn ensure_unlocked(&self) -> Result<(), HemliError> {
+        match *self.key.lock().unwrap() {
+            KeyState::Pending => {}
+            KeyState::Unlocked(_) => return Ok(()),
+            KeyState::Locked => return Err(HemliError::Locked),
+        }
+        let passphrase = read_passphrase(!self.path.exists())?;
+        self.unlock(&passphrase)
+    }
+
+    fn load(&self) -> Result<Secrets, HemliError> {
+        self.ensure_unlocked()?;
+        let Some(file) = read_envelope(&self.path)? else {
+            return Ok(Secrets::new());
+        };
+        let mut guard = self.key.lock().unwrap();
+        // The store may have been locked since it was unlocked above
+        let KeyState::Unlocked(unlocked) = &mut *guard else {
+            return Err(HemliError::Locked);
+        };
+        // Another process created the file with a fresh salt
+        if unlocked.kdf != file.kdf {
+            let Some(passphrase) = unlocked.passphrase.take() else {
+                // The passphrase is gone, so the file has been recreated
+                // since the key was checked against it
+                *guard = KeyState::Locked;
+                return Err(HemliError::Locked);
+            };
+            unlocked.key.as_mut_slice().zeroize();
+            unlocked.key = file.kdf.derive(&passphrase)?;
+            unlocked.kdf = file.kdf.clone();
+        }
+        let plaintext = Zeroizing::new(decrypt(&file, &unlocked.key, &self.path)?);
+        // The key is known to match the file
+        unlocked.passphrase = None;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    fn save(&self, secrets: &Secrets) -> Result<(), HemliError> {
+        let mut guard = self.key.lock().unwrap();
+        let KeyState::Unlocked(unlocked) = &mut *guard else {
+            return Err(HemliError::Locked);
+        };
+        let plaintext = Zeroizing::new(serde_json::to_vec(secrets)?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&unlocked.key)
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| HemliError::SecretsFile("encryption failed".into()))?;
+        let file = EncryptedFile {
+            version: FORMAT_VERSION,
+            kdf: unlocked.kdf.clone(),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+        fsutil::write_atomic(&self.path, &serde_json::to_vec_pretty(&file)?)?;
+        // The file now uses this key
+        unlocked.passphrase = None;
+        Ok(())
+    }
+}
+
+impl SecretStore for FileStore {
+    fn get(&self, namespace: &str, name: &str) -> Result<Option<StoredSecret>, HemliError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let mut secrets = self.load()?;
+        Ok(secrets.get_mut(namespace).and_then(|ns| ns.remove(name)))
+    }
+
+    fn set(&self, namespace: &str, name: &str, secret: &StoredSecret) -> Result<(), HemliError> {
+        // Unlock first so a passphrase prompt doesn't hold up other processes
+        self.ensure_unlocked()?;
+        let _lock = FileLock::acquire(&fsutil::lock_path(&self.path))?;
+        let mut secrets = self.load()?;
+        secrets
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(name.to_string(), secret.clone());
+        self.save(&secrets)
+    }
+
+    fn delete(&self, namespace: &str, name: &str) -> Result<(), HemliError> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        // Unlock first so a passphrase prompt doesn't hold up other processes
+        self.ensure_unlocked()?;
+        let _lock = FileLock::acquire(&fsutil::lock_path(&self.path))?;
+        let mut secrets = self.load()?;
+        let Some(ns) = secrets.get_mut(namespace) else {
+            return Ok(());
+        };
+        if ns.remove(name).is_none() {
+            return Ok(());
+        }
+        if ns.is_empty() {
+            secrets.remove(namespace);
+        }
+        self.save(&secrets)
+    }
+
+    fn list(&self) -> Result<Option<Vec<(String, String)>>, HemliError> {
+        if !self.path.exists() {
+            return Ok(Some(Vec::new()));
+        }
+        let secrets = self.load()?;
+        Ok(Some(
+            secrets
+                .into_iter()
+                .flat_map(|(ns, names)| names.into_keys().map(move |name| (ns.clone(), name)))
+                .collect(),
+        ))
+    }
+
+    /// Derive the key for `passphrase`, verifying it against the existing
+    /// file if there is one.
+    fn unlock(&self, passphrase: &str) -> Result<(), HemliError> {
+        let unlocked = match read_envelope(&self.path)? {
+            Some(file) => {
+                let mut key = file.kdf.derive(passphrase)?;
+                if let Err(e) = decrypt(&file, &key, &self.path) {
+                    key.as_mut_slice().zeroize();
+                    return Err(e);
+                }
+                Unlocked {
+                    kdf: file.kdf,
+                    key,
+                    passphrase: None,
+                }
+            }
+            None => {
+                let kdf = KdfParams::generate();
+                let key = kdf.derive(passphrase)?;
+                Unlocked {
+                    kdf,
+                    key,
+                    passphrase: Some(Zeroizing::new(passphrase.to_string())),
+                }
+            }
+        };
+        *self.key.lock().unwrap() = KeyState::Unlocked(unlocked);
+        Ok(())
+    }
+
+    /// Wipe the key from memory.
+    fn lock(&self) {
+        *self.key.lock().unwrap() = KeyState::Locked;
+    }
+
+    /// Read the passphrase, confirming it if there is no file yet, unless
+    /// the store is unlocked already.
+    fn authenticate(&self) -> Result<(), HemliError> {
+        self.ensure_unlocked()
+    }
+}
+
+/// The passphrase from `HEMLI_PASSPHRASE`, or prompted for on the terminal.
+/// It is wiped from memory when dropped.
+pub fn read_passphrase(confirm: bool) -> Result<Zeroizing<String>, HemliError> {
+    if let Ok(passphrase) = std::env::var("HEMLI_PASSPHRASE") {
+        return Ok(Zeroizing::new(passphrase));
+    }
+    let prompt = |prompt| {
+        rpassword::prompt_password(prompt)
+            .map(Zeroizing::new)
+            .map_err(|_| HemliError::NoPassphrase)
+    };
+    let passphrase = prompt("hemli passphrase: ")?;
+    if confirm {
+        let again = prompt("confirm passphrase: ")?;
+        if again != passphrase {
+            return Err(HemliError::PassphraseMismatch);
+        }
+    }
+    Ok(passphrase)
+}
+
+fn read_envelope(path: &Path) -> Result<Option<EncryptedFile>, HemliError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file: EncryptedFile = serde_json::from_slice(&fs::read(path)?)?;
+    if file.version != FORMAT_VERSION {
+        return Err(HemliError::SecretsFile(format!(
+            "unsupported format version {}",
+            file.version
+        )));
+    }
+    Ok(Some(file))
+}
+
+fn decrypt(file: &EncryptedFile, key: &Key, path: &Path) -> Result<Vec<u8>, HemliError> {
+    let nonce = BASE64
+        .decode(&file.nonce)
+        .map_err(|e| HemliError::SecretsFile(format!("invalid nonce: {e}")))?;
+    if nonce.len() != 12 {
+        return Err(HemliError::SecretsFile("invalid nonce length".into()));
+    }
+    let ciphertext = BASE64
+        .decode(&file.ciphertext)
+        .map_err(|e| HemliError::SecretsFile(format!("invalid ciphertext: {e}")))?;
+    ChaCha20Poly1305::new(key)
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| HemliError::Decrypt(path.display().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unlocked_store(dir: &Path, passphrase: &str) -> FileStore {
+        let store = FileStore::new(dir.join("secrets.enc"));
+        store.unlock(passphrase).unwrap();
+        store
+    }
+
+    #[test]
+    fn get_set_delete_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = unlocked_store(dir.path(), "hunter2");
+
+        assert!(store.get("ns", "sec").unwrap().is_none());
+
+        let secret = StoredSecret::new("test-value".into(), None, None, None);
+        store.set("ns", "sec", &secret).unwrap();
+        assert_eq!(store.get("ns", "sec").unwrap().unwrap().value, "test-value");
+
+        store.delete("ns", "sec").unwrap();
+        assert!(store.get("ns", "sec").unwrap().is_none());
+    }
+
+    #[test]
+    fn persists_across_instances() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = StoredSecret::new("persisted".into(), None, None, Some(60));
+        unlocked_store(dir.path(), "hunter2")
+            .set("ns", "sec", &secret)
+            .unwrap();
+
+        let reopened = unlocked_store(dir.path(), "hunter2");
+        let loaded = reopened.get("ns", "sec").unwrap().unwrap();
+        assert_eq!(loaded.value, "persisted");
+        assert_eq!(loaded.ttl_seconds, Some(60));
+    }
+
+    #[test]
+    fn wrong_passphrase_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = StoredSecret::new("val".into(), None, None, None);
+        unlocked_store(dir.path(), "right")
+            .set("ns", "sec", &secret)
+            .unwrap();
+
+        let store = FileStore::new(dir.path().join("secrets.enc"));
+        match store.unlock("wrong").unwrap_err() {
+            HemliError::Decrypt(_) => {}
+            other => panic!("expected Decrypt, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn locked_store_needs_explicit_unlock() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = unlocked_store(dir.path(), "hunter2");
+        let secret = StoredSecret::new("val".into(), None, None, None);
+        store.set("ns", "sec", &secret).unwrap();
+
+        store.lock();
+        assert!(matches!(store.get("ns", "sec"), Err(HemliError::Locked)));
+        assert!(matches!(
+            store.set("ns", "sec", &secret),
+            Err(HemliError::Locked)
+        ));
+        assert!(store.unlock("wrong").is_err());
+        assert!(matches!(store.get("ns", "sec"), Err(HemliError::Locked)));
+
+        store.unlock("hunter2").unwrap();
+        assert_eq!(store.get("ns", "sec").unwrap().unwrap().value, "val");
+    }
+
+    #[test]
+    fn first_writer_sets_the_salt_for_others_unlocked_without_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = unlocked_store(dir.path(), "hunter2");
+        let second = unlocked_store(dir.path(), "hunter2");
+        let secret = StoredSecret::new("val".into(), None, None, None);
+
+        first.set("ns", "a", &secret).unwrap();
+        second.set("ns", "b", &secret).unwrap();
+        assert_eq!(first.list().unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn recreated_file_needs_unlocking_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = unlocked_store(dir.path(), "hunter2");
+        let secret = StoredSecret::new("val".into(), None, None, None);
+        store.set("ns", "sec", &secret).unwrap();
+
+        // Another process recreates the file under a fresh salt
+        fs::remove_file(dir.path().join("secrets.enc")).unwrap();
+        unlocked_store(dir.path(), "hunter2")
+            .set("ns", "sec", &secret)
+            .unwrap();
+
+        assert!(matches!(store.get("ns", "sec"), Err(HemliError::Locked)));
+        store.unlock("hunter2").unwrap();
+        assert_eq!(store.get("ns", "sec").unwrap().unwrap().value, "val");
+    }
+
+    #[test]
+    fn file_does_not_contain_plaintext() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = unlocked_store(dir.path(), "hunter2");
+        let secret = StoredSecret::new("super-secret-value".into(), None, None, None);
+        store.set("my-namespace", "sec", &secret).unwrap();
+
+        let contents = fs::read_to_string(dir.path().join("secrets.enc")).unwrap();
+        assert!(!contents.contains("super-secret-value"));
+        assert!(!contents.contains("my-namespace"));
+    }
+
+    #[test]
+    fn list_enumerates_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = unlocked_store(dir.path(), "hunter2");
+        assert_eq!(store.list().unwrap(), Some(vec![]));
+
+        let secret = StoredSecret::new("val".into(), None, None, None);
+        store.set("ns2", "b", &secret).unwrap();
+        store.set("ns1", "a", &secret).unwrap();
+        assert_eq!(
+            store.list().unwrap(),
+            Some(vec![
+                ("ns1".to_string(), "a".to_string()),
+                ("ns2".to_string(), "b".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn delete_nonexistent_is_ok() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = unlocked_store(dir.path(), "hunter2");
+        store.delete("ns", "missing").unwrap();
+        assert!(!dir.path().join("secrets.enc").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = unlocked_store(dir.path(), "hunter2");
+        let secret = StoredSecret::new("val".into(), None, None, None);
+        store.set("ns", "sec", &secret).unwrap();
+
+        let mode = fs::metadata(dir.path().join("secrets.enc"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
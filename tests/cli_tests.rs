@@ -214,3 +214,1243 @@ fn test_delete_nonexistent_succeeds() {
         .assert()
         .success();
 }
+
+/// hemli using the encrypted-file backend with its data directory redirected
+/// into `data_home`. `dirs::data_dir` only honours `XDG_DATA_HOME` on Linux.
+fn hemli_file_cmd(data_home: &std::path::Path) -> Command {
+    let mut cmd = hemli_cmd();
+    cmd.env("HEMLI_BACKEND", "file")
+        .env("HEMLI_PASSPHRASE", "e2e-passphrase")
+        .env("XDG_DATA_HOME", data_home)
+        .env("XDG_RUNTIME_DIR", data_home);
+    cmd
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_file_backend_store_and_retrieve() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo from-file"])
+        .assert()
+        .success()
+        .stdout("from-file");
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--no-refresh"])
+        .assert()
+        .success()
+        .stdout("from-file");
+
+    assert!(data.path().join("hemli").join("secrets.enc").exists());
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_file_backend_wrong_passphrase() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo locked"])
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .env("HEMLI_PASSPHRASE", "not-the-passphrase")
+        .args(["get", "-n", "ns", "sec", "--no-refresh"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("wrong passphrase"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_file_backend_delete() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo gone"])
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args(["delete", "-n", "ns", "sec"])
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--no-refresh"])
+        .assert()
+        .failure();
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_run_injects_secrets() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "db_password", "--source-sh", "echo pw"])
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args([
+            "run",
+            "-n",
+            "ns",
+            "--env",
+            "DB_PASSWORD=db_password",
+            "--env",
+            "API_KEY=api_key",
+            "--",
+            "sh",
+            "-c",
+            "printf '%s' \"$DB_PASSWORD\"",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no source command"));
+
+    hemli_file_cmd(data.path())
+        .args([
+            "run",
+            "-n",
+            "ns",
+            "--env",
+            "DB_PASSWORD=db_password",
+            "--",
+            "sh",
+            "-c",
+            "printf '%s' \"$DB_PASSWORD\"; exit 3",
+        ])
+        .assert()
+        .code(3)
+        .stdout("pw");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_manifest_provides_namespace_and_source() {
+    let data = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    std::fs::write(
+        project.path().join("hemli.toml"),
+        "namespace = \"proj\"\n\n[secrets.db_password]\nsource_sh = \"echo from-manifest\"\n",
+    )
+    .unwrap();
+    let subdir = project.path().join("src");
+    std::fs::create_dir(&subdir).unwrap();
+
+    hemli_file_cmd(data.path())
+        .current_dir(&subdir)
+        .env_remove("HEMLI_NAMESPACE")
+        .args(["get", "db_password"])
+        .assert()
+        .success()
+        .stdout("from-manifest");
+
+    // Stored under the manifest namespace
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "proj", "db_password", "--no-refresh"])
+        .assert()
+        .success()
+        .stdout("from-manifest");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_invalid_manifest_is_ignored_with_a_warning() {
+    let data = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    std::fs::write(project.path().join("hemli.toml"), "namespace = [").unwrap();
+
+    hemli_file_cmd(data.path())
+        .current_dir(project.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo explicit"])
+        .assert()
+        .success()
+        .stdout("explicit")
+        .stderr(predicate::str::contains("warning: invalid manifest"));
+
+    // Only a value it would have provided is missing
+    hemli_file_cmd(data.path())
+        .current_dir(project.path())
+        .env_remove("HEMLI_NAMESPACE")
+        .args(["get", "sec"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no namespace given"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_set_from_stdin() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "manual"])
+        .write_stdin("hunter2\n")
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "manual"])
+        .assert()
+        .success()
+        .stdout("hunter2");
+
+    hemli_file_cmd(data.path())
+        .args(["inspect", "-n", "ns", "manual"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("source_command").not());
+
+    hemli_file_cmd(data.path())
+        .args(["list", "-n", "ns"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("manual"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_set_keeps_binary_values_and_existing_settings() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "binary"])
+        .write_stdin(&b"\xff\x00key\n"[..])
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "binary"])
+        .assert()
+        .success()
+        .stdout(&b"\xff\x00key"[..]);
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sourced", "--source-sh", "echo fetched"])
+        .args(["--ttl", "1h", "--retries", "2"])
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "sourced"])
+        .write_stdin("manual")
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["inspect", "-n", "ns", "sourced"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""value": "manual""#))
+        .stdout(predicate::str::contains("echo fetched"))
+        .stdout(predicate::str::contains(r#""ttl_seconds": 3600"#))
+        .stdout(predicate::str::contains(r#""retries": 2"#));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_set_rejects_empty_value() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "empty"])
+        .write_stdin("\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("empty secret"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_set_rejects_hash_in_name() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "api#key"])
+        .write_stdin("value")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("contains '#'"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_list_formats() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args([
+            "get",
+            "-n",
+            "ns",
+            "sec",
+            "--source-sh",
+            "echo listed",
+            "--ttl",
+            "3600",
+        ])
+        .assert()
+        .success();
+
+    let output = hemli_file_cmd(data.path())
+        .args(["list", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json[0]["namespace"], "ns");
+    assert_eq!(json[0]["secret"], "sec");
+    assert_eq!(json[0]["ttl_seconds"], 3600);
+    assert_eq!(json[0]["source_type"], "sh");
+    assert!(json[0]["expires_at"].is_string());
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "{namespace}/{secret}"])
+        .assert()
+        .success()
+        .stdout("ns/sec\n");
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "table"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("NAMESPACE"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_list_expiry_filters() {
+    let data = tempfile::tempdir().unwrap();
+
+    for (secret, ttl) in [("short", "1"), ("long", "3600")] {
+        hemli_file_cmd(data.path())
+            .args([
+                "get",
+                "-n",
+                "ns",
+                secret,
+                "--source-sh",
+                "echo v",
+                "--ttl",
+                ttl,
+            ])
+            .assert()
+            .success();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--expired", "--format", "{secret}"])
+        .assert()
+        .success()
+        .stdout("short\n");
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--valid", "--format", "{secret}"])
+        .assert()
+        .success()
+        .stdout("long\n");
+
+    hemli_file_cmd(data.path())
+        .args([
+            "list",
+            "--expiring-within",
+            "2h",
+            "--format",
+            "{secret}={status}",
+        ])
+        .assert()
+        .success()
+        .stdout("long=valid\n");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_refresh_refetches_and_reports_failures() {
+    let data = tempfile::tempdir().unwrap();
+    let source = data.path().join("source.txt");
+    std::fs::write(&source, "v1").unwrap();
+    let cat_source = format!("cat {}", source.display());
+    let flaky = format!("test -e {}.ok && echo fine", source.display());
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "a", "--source-sh", &cat_source])
+        .assert()
+        .success()
+        .stdout("v1");
+    std::fs::write(format!("{}.ok", source.display()), "").unwrap();
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "b", "--source-sh", &flaky])
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "manual"])
+        .write_stdin("x")
+        .assert()
+        .success();
+
+    std::fs::write(&source, "v2").unwrap();
+    std::fs::remove_file(format!("{}.ok", source.display())).unwrap();
+
+    // Nothing has expired, so the default mode does nothing
+    hemli_file_cmd(data.path())
+        .args(["refresh", "-n", "ns"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Refreshed 0 of 0"));
+
+    hemli_file_cmd(data.path())
+        .args(["refresh", "-n", "ns", "--all", "--jobs", "2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("refreshed ns/a"))
+        .stderr(predicate::str::contains("failed ns/b"))
+        .stderr(predicate::str::contains(
+            "skipped ns/manual: no stored source",
+        ));
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "a", "--no-refresh"])
+        .assert()
+        .success()
+        .stdout("v2");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_refresh_expired_leaves_refresh_ahead_window() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo v"])
+        .args(["--ttl", "1h", "--refresh-ahead", "2h"])
+        .assert()
+        .success();
+
+    // Due ahead of expiry, but not expired
+    hemli_file_cmd(data.path())
+        .args(["refresh", "-n", "ns", "--expired"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Refreshed 0 of 0"));
+    hemli_file_cmd(data.path())
+        .args(["refresh", "-n", "ns"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Refreshed 1 of 1"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_purge_namespace_with_dry_run() {
+    let data = tempfile::tempdir().unwrap();
+
+    for (ns, secret) in [("old", "a"), ("old", "b"), ("keep", "c")] {
+        hemli_file_cmd(data.path())
+            .args(["set", "-n", ns, secret])
+            .write_stdin("value")
+            .assert()
+            .success();
+    }
+
+    hemli_file_cmd(data.path())
+        .args(["purge", "-n", "old", "--dry-run"])
+        .assert()
+        .success()
+        .stdout("old\ta\nold\tb\n");
+
+    // Dry run removed nothing
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "old", "a", "--no-refresh"])
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args(["purge", "-n", "old"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Purged 2 of 2"));
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "old", "a", "--no-refresh"])
+        .assert()
+        .failure();
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "{namespace}/{secret}"])
+        .assert()
+        .success()
+        .stdout("keep/c\n");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_purge_expired() {
+    let data = tempfile::tempdir().unwrap();
+
+    for (secret, ttl) in [("short", "1"), ("long", "3600")] {
+        hemli_file_cmd(data.path())
+            .args(["set", "-n", "ns", secret, "--ttl", ttl])
+            .write_stdin("value")
+            .assert()
+            .success();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+
+    hemli_file_cmd(data.path())
+        .args(["purge", "--expired"])
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "{secret}"])
+        .assert()
+        .success()
+        .stdout("long\n");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_doctor_notes_skipped_unindexed_check_on_keyring() {
+    let data = tempfile::tempdir().unwrap();
+
+    // With an empty index, doctor never touches the keyring itself
+    hemli_cmd()
+        .env("HEMLI_BACKEND", "keyring")
+        .env("XDG_DATA_HOME", data.path())
+        .env("XDG_RUNTIME_DIR", data.path())
+        .arg("doctor")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "secrets missing from the index were not checked",
+        ));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_doctor_rebuilds_deleted_index() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "sec"])
+        .write_stdin("value")
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .arg("doctor")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("note:").not());
+
+    std::fs::remove_file(data.path().join("hemli").join("index.json")).unwrap();
+
+    hemli_file_cmd(data.path())
+        .arg("doctor")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unindexed ns/sec"));
+
+    hemli_file_cmd(data.path())
+        .args(["doctor", "--fix"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("fixed unindexed ns/sec"));
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "{namespace}/{secret}"])
+        .assert()
+        .success()
+        .stdout("ns/sec\n");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_corrupt_index_is_recovered() {
+    let data = tempfile::tempdir().unwrap();
+    let index = data.path().join("hemli").join("index.json");
+    std::fs::create_dir_all(index.parent().unwrap()).unwrap();
+    std::fs::write(&index, "{\"entries\": [").unwrap();
+
+    hemli_file_cmd(data.path())
+        .arg("list")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("corrupt index"));
+
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "sec"])
+        .write_stdin("value")
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "{secret}"])
+        .assert()
+        .success()
+        .stdout("sec\n")
+        .stderr("");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_concurrent_sets_keep_every_entry() {
+    let data = tempfile::tempdir().unwrap();
+
+    let children: Vec<_> = (0..8)
+        .map(|i| {
+            let mut cmd = std::process::Command::new(assert_cmd::cargo::cargo_bin!("hemli"));
+            cmd.env("HEMLI_BACKEND", "file")
+                .env("HEMLI_PASSPHRASE", "e2e-passphrase")
+                .env("XDG_DATA_HOME", data.path())
+                .args([
+                    "get",
+                    "-n",
+                    "ns",
+                    &format!("sec{i}"),
+                    "--source-sh",
+                    "echo v",
+                ])
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let output = hemli_file_cmd(data.path())
+        .args(["list", "--format", "{secret}"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 8);
+
+    hemli_file_cmd(data.path()).arg("doctor").assert().success();
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_concurrent_gets_fetch_once() {
+    let data = tempfile::tempdir().unwrap();
+    let calls = data.path().join("calls.log");
+    let source = format!("echo call >> {}; sleep 0.5; echo shared", calls.display());
+
+    let children: Vec<_> = (0..5)
+        .map(|_| {
+            std::process::Command::new(assert_cmd::cargo::cargo_bin!("hemli"))
+                .env("HEMLI_BACKEND", "file")
+                .env("HEMLI_PASSPHRASE", "e2e-passphrase")
+                .env("XDG_DATA_HOME", data.path())
+                .args(["get", "-n", "ns", "sec", "--source-sh", &source])
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for child in children {
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"shared");
+    }
+
+    let calls = std::fs::read_to_string(calls).unwrap();
+    assert_eq!(calls.lines().count(), 1);
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_source_timeout() {
+    let data = tempfile::tempdir().unwrap();
+    let start = std::time::Instant::now();
+
+    // Global default from the environment
+    hemli_file_cmd(data.path())
+        .env("HEMLI_SOURCE_TIMEOUT", "1s")
+        .args(["get", "-n", "ns", "slow", "--source-sh", "sleep 30"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("timed out after 1s"));
+
+    // Per-secret timeout is stored and applies to later refreshes
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo ok"])
+        .args(["--source-timeout", "1s"])
+        .assert()
+        .success()
+        .stdout("ok");
+    hemli_file_cmd(data.path())
+        .args(["edit", "-n", "ns", "sec", "--source-sh", "sleep 30"])
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["refresh", "-n", "ns", "--all"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "failed ns/sec: source command timed out",
+        ));
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(20));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_source_with_timeout_reads_stdin() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "cat"])
+        .args(["--source-timeout", "5s"])
+        .write_stdin("from-stdin")
+        .assert()
+        .success()
+        .stdout("from-stdin");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_retries_are_stored_and_used() {
+    let data = tempfile::tempdir().unwrap();
+    let counter = data.path().join("attempts");
+    // Fails on every attempt but the third
+    let flaky = format!(
+        "echo x >> {0}; test $(wc -l < {0}) -eq 3 && echo ok",
+        counter.display()
+    );
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", &flaky])
+        .args(["--retries", "2", "--retry-backoff", "10ms"])
+        .assert()
+        .success()
+        .stdout("ok");
+
+    // The stored policy applies to refreshes, which start counting afresh
+    std::fs::remove_file(&counter).unwrap();
+    hemli_file_cmd(data.path())
+        .args(["refresh", "-n", "ns", "--all"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("refreshed ns/sec"));
+    let attempts = std::fs::read_to_string(&counter).unwrap();
+    assert_eq!(attempts.lines().count(), 3);
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_stale_if_error_serves_expired_value() {
+    let data = tempfile::tempdir().unwrap();
+    let source = data.path().join("source.txt");
+    std::fs::write(&source, "cached").unwrap();
+    let cat_source = format!("cat {}", source.display());
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", &cat_source])
+        .args(["--ttl", "1", "--stale-if-error"])
+        .assert()
+        .success()
+        .stdout("cached");
+    std::fs::remove_file(&source).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+
+    // The stored setting applies without repeating the flag
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec"])
+        .assert()
+        .success()
+        .stdout("cached")
+        .stderr(predicate::str::contains(
+            "warning: refreshing ns/sec failed",
+        ))
+        .stderr(predicate::str::contains("(expired "));
+
+    hemli_file_cmd(data.path())
+        .args(["edit", "-n", "ns", "sec", "--stale-if-error=1s"])
+        .assert()
+        .success();
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("source command failed"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_stale_if_error_on_forced_refresh_of_valid_value() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo cached"])
+        .args(["--ttl", "1h", "--stale-if-error"])
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--force-refresh"])
+        .args(["--source-sh", "exit 1"])
+        .assert()
+        .success()
+        .stdout("cached")
+        .stderr(predicate::str::contains("(expires "));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_source_cmd_argv() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-cmd", "--"])
+        .args(["printf", "%s", "Dev Vault \"x\""])
+        .assert()
+        .success()
+        .stdout("Dev Vault \"x\"");
+
+    hemli_file_cmd(data.path())
+        .args(["inspect", "-n", "ns", "sec"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""Dev Vault \"x\"""#));
+
+    // The stored argv is reused when refreshing
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--force-refresh"])
+        .assert()
+        .success()
+        .stdout("Dev Vault \"x\"");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_output_modes_preserve_bytes() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "pem", "--output-mode", "raw"])
+        .args(["--source-sh", r"printf -- '-----KEY-----\n\n'"])
+        .assert()
+        .success()
+        .stdout("-----KEY-----\n\n");
+
+    let binary = r"printf '\377\000\001'";
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "bin", "--output-mode", "raw"])
+        .args(["--source-sh", binary])
+        .assert()
+        .success()
+        .stdout(predicate::eq(&[0xff_u8, 0x00, 0x01][..]));
+    // Byte-exact from the cache too
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "bin", "--no-refresh"])
+        .assert()
+        .success()
+        .stdout(predicate::eq(&[0xff_u8, 0x00, 0x01][..]));
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "b64", "--output-mode", "base64"])
+        .args(["--source-sh", binary])
+        .assert()
+        .success()
+        .stdout("/wAB");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_field_extraction() {
+    let data = tempfile::tempdir().unwrap();
+    let json = r#"echo '{"data": {"data": {"password": "hunter2"}}}'"#;
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--field", "data.data.password"])
+        .args(["--source-sh", json])
+        .assert()
+        .success()
+        .stdout("hunter2");
+
+    // The stored field is applied again on refresh
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--force-refresh"])
+        .assert()
+        .success()
+        .stdout("hunter2");
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "other", "--field", "/data/username"])
+        .args(["--source-sh", json])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "field '/data/username' not found in source output",
+        ));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_multi_field_secret() {
+    let data = tempfile::tempdir().unwrap();
+    let calls = data.path().join("calls.log");
+    let source = format!(
+        r#"echo x >> {}; echo '{{"data": {{"username": "app", "password": "hunter2"}}}}'"#,
+        calls.display()
+    );
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "db#password", "--source-sh", &source])
+        .args(["--fields", "user=data.username"])
+        .args(["--fields", "password=/data/password"])
+        .assert()
+        .success()
+        .stdout("hunter2");
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "db#user"])
+        .assert()
+        .success()
+        .stdout("app");
+
+    hemli_file_cmd(data.path())
+        .args(["run", "-n", "ns", "-e", "U=db#user", "-e", "P=db#password"])
+        .args(["--", "sh", "-c", "printf %s:%s \"$U\" \"$P\""])
+        .assert()
+        .success()
+        .stdout("app:hunter2");
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "db#host"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "no field 'host' (available: password, user)",
+        ));
+
+    // One source run served every field
+    let calls = std::fs::read_to_string(&calls).unwrap();
+    assert_eq!(calls.lines().count(), 1);
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_ttl_from_source_lease() {
+    let data = tempfile::tempdir().unwrap();
+    let calls = data.path().join("calls.log");
+    let source = format!(
+        r#"echo x >> {}; echo '{{"token": "abc", "lease_duration": 3600}}'"#,
+        calls.display()
+    );
+    let call_count = || std::fs::read_to_string(&calls).unwrap().lines().count();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "tok", "--source-sh", &source])
+        .args(["--field", "token", "--ttl-from", "json:lease_duration"])
+        .assert()
+        .success()
+        .stdout("abc");
+
+    // The lease has not run out, so the cached value is served
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "tok"])
+        .assert()
+        .success()
+        .stdout("abc");
+    assert_eq!(call_count(), 1);
+
+    // A margin as long as the lease expires the secret at once
+    hemli_file_cmd(data.path())
+        .args(["edit", "-n", "ns", "tok", "--ttl-margin", "1h"])
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "tok"])
+        .assert()
+        .success()
+        .stdout("abc");
+    assert_eq!(call_count(), 2);
+
+    // An unreadable expiry warns and falls back to the TTL
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "other", "--source-sh", "echo '{}'"])
+        .args(["--ttl", "60", "--ttl-from", "json:lease_duration"])
+        .assert()
+        .success()
+        .stdout("{}")
+        .stderr(predicate::str::contains(
+            "warning: ns/other: field 'lease_duration' not found in source output; using its TTL",
+        ));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_refresh_ahead_refreshes_in_background() {
+    let data = tempfile::tempdir().unwrap();
+    let calls = data.path().join("calls.log");
+    let source = format!("echo x >> {0}; wc -l < {0} | tr -d ' '", calls.display());
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", &source])
+        .args(["--ttl", "3600", "--refresh-ahead", "2h"])
+        .assert()
+        .success()
+        .stdout("1");
+
+    // Within the window: the cached value comes back at once...
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec"])
+        .assert()
+        .success()
+        .stdout("1");
+
+    // ...and a background refresh stores a new one for the next caller
+    let fresh = (0..100).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let output = hemli_file_cmd(data.path())
+            .args(["get", "-n", "ns", "sec", "--no-refresh"])
+            .output()
+            .unwrap();
+        output.stdout == b"2"
+    });
+    assert!(fresh, "background refresh did not store a new value");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_friendly_ttl() {
+    let data = tempfile::tempdir().unwrap();
+
+    hemli_file_cmd(data.path())
+        .args(["set", "-n", "ns", "sec", "--ttl", "12h"])
+        .write_stdin("value")
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args(["inspect", "-n", "ns", "sec"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""ttl_seconds": 43200"#))
+        .stdout(predicate::str::contains(r#""ttl": "12h""#));
+
+    hemli_file_cmd(data.path())
+        .args(["edit", "-n", "ns", "sec", "--ttl", "P1DT30M"])
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "{secret} {ttl}"])
+        .assert()
+        .success()
+        .stdout("sec 1d 30m\n");
+
+    hemli_file_cmd(data.path())
+        .args(["edit", "-n", "ns", "sec", "--ttl", "0"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("must be greater than zero"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_absolute_and_daily_expiry() {
+    let data = tempfile::tempdir().unwrap();
+    let calls = data.path().join("calls.log");
+    let source = format!("echo x >> {}; echo value", calls.display());
+    let call_count = || std::fs::read_to_string(&calls).unwrap().lines().count();
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", &source])
+        .args(["--ttl", "1h", "--expires-daily-at", "03:00[UTC]"])
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["inspect", "-n", "ns", "sec"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""daily_expiry": "03:00[UTC]""#));
+
+    // A fetch only takes fixed times in the future...
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec"])
+        .args(["--expires-at", "2020-01-01T00:00:00Z"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is in the past"));
+
+    // ...while an edit can set one that has passed, expiring the secret now
+    hemli_file_cmd(data.path())
+        .args(["edit", "-n", "ns", "sec", "--clear-expires-daily-at"])
+        .args(["--expires-at", "2020-01-01T00:00:00Z"])
+        .assert()
+        .success();
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "{status}"])
+        .assert()
+        .success()
+        .stdout("expired\n");
+
+    // Once passed, the fixed time is re-fetched once and then left to the TTL
+    for _ in 0..2 {
+        hemli_file_cmd(data.path())
+            .args(["get", "-n", "ns", "sec"])
+            .assert()
+            .success();
+    }
+    assert_eq!(call_count(), 2);
+    hemli_file_cmd(data.path())
+        .args(["list", "--format", "{status}"])
+        .assert()
+        .success()
+        .stdout("valid\n");
+}
+
+/// Start `hemli agent` for the file backend and wait for its socket.
+fn spawn_file_agent(data_home: &std::path::Path) -> std::process::Child {
+    let agent = std::process::Command::new(env!("CARGO_BIN_EXE_hemli"))
+        .arg("agent")
+        .env("HEMLI_BACKEND", "file")
+        .env("HEMLI_PASSPHRASE", "e2e-passphrase")
+        .env("XDG_DATA_HOME", data_home)
+        .env("XDG_RUNTIME_DIR", data_home)
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let socket = data_home.join("hemli/agent-file.sock");
+    let listening = (0..100).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        socket.exists()
+    });
+    assert!(listening, "agent did not create its socket");
+    agent
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_agent_serves_secrets_from_memory() {
+    let data = tempfile::tempdir().unwrap();
+    let socket = data.path().join("hemli/agent-file.sock");
+    let mut agent = spawn_file_agent(data.path());
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo from-agent"])
+        .assert()
+        .success()
+        .stdout("from-agent");
+
+    // The agent holds the secret even once the file is gone
+    std::fs::remove_file(data.path().join("hemli/secrets.enc")).unwrap();
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--no-refresh"])
+        .assert()
+        .success()
+        .stdout("from-agent");
+
+    hemli_file_cmd(data.path())
+        .args(["agent", "--status"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 secrets cached"));
+
+    hemli_file_cmd(data.path())
+        .args(["agent", "--stop"])
+        .assert()
+        .success();
+    assert!(agent.wait().unwrap().success());
+    assert!(!socket.exists());
+
+    // Without the agent, hemli reads the (now empty) backend directly
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--no-refresh"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not found"));
+    hemli_file_cmd(data.path())
+        .args(["agent", "--status"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no agent is listening"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_agent_removes_its_socket_when_terminated() {
+    let data = tempfile::tempdir().unwrap();
+    let socket = data.path().join("hemli/agent-file.sock");
+    let mut agent = spawn_file_agent(data.path());
+
+    std::process::Command::new("kill")
+        .args(["-TERM", &agent.id().to_string()])
+        .status()
+        .unwrap();
+    agent.wait().unwrap();
+    assert!(!socket.exists());
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_agent_lock_and_unlock() {
+    let data = tempfile::tempdir().unwrap();
+    let mut agent = spawn_file_agent(data.path());
+
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--source-sh", "echo held"])
+        .assert()
+        .success();
+
+    hemli_file_cmd(data.path()).arg("lock").assert().success();
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("locked; unlock it again first"));
+    hemli_file_cmd(data.path())
+        .args(["agent", "--status"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("locked"));
+
+    // Unlocking needs the right passphrase
+    hemli_file_cmd(data.path())
+        .env("HEMLI_PASSPHRASE", "wrong")
+        .arg("unlock")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("wrong passphrase"));
+    hemli_file_cmd(data.path()).arg("unlock").assert().success();
+    hemli_file_cmd(data.path())
+        .args(["agent", "--status"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 secrets cached"));
+    hemli_file_cmd(data.path())
+        .args(["get", "-n", "ns", "sec", "--no-refresh"])
+        .assert()
+        .success()
+        .stdout("held");
+
+    hemli_file_cmd(data.path())
+        .args(["agent", "--stop"])
+        .assert()
+        .success();
+    agent.wait().unwrap();
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore)]
+fn test_keyring_agent_cannot_be_locked() {
+    for args in [
+        &["lock"][..],
+        &["unlock"],
+        &["agent", "--idle-timeout", "15m"],
+    ] {
+        hemli_cmd()
+            .args(["--backend", "keyring"])
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("cannot be locked"));
+    }
+}